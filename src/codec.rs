@@ -0,0 +1,215 @@
+//! Canonical binary encoding used as hash pre-image, wire and storage format.
+//!
+//! Integers are fixed-width little-endian, strings and sequences are prefixed
+//! with their length as `u32`, `Option` is a `0`/`1` tag byte followed by the
+//! value and enums are a tag byte followed by their fields. Top-level types
+//! (`Block`, `Transaction`, `Account`) start with [`ENCODING_VERSION`].
+
+use crate::traits::{Decodable, Encodable};
+use crate::types::DecodeError;
+use ed25519_dalek::{PublicKey, Signature, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use num::bigint::Sign;
+use num::BigInt;
+
+pub const ENCODING_VERSION: u8 = 1;
+
+pub(crate) fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+pub(crate) fn encode_version(out: &mut Vec<u8>) {
+    ENCODING_VERSION.encode_to(out);
+}
+
+pub(crate) fn decode_version(input: &mut &[u8]) -> Result<(), DecodeError> {
+    match u8::decode_from(input)? {
+        ENCODING_VERSION => Ok(()),
+        version => Err(DecodeError::UnsupportedVersion(version)),
+    }
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    (len as u32).encode_to(out);
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize, DecodeError> {
+    let len = u32::decode_from(input)? as usize;
+    if len > input.len() {
+        return Err(DecodeError::LengthOverflow);
+    }
+    Ok(len)
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {
+        $(
+            impl Encodable for $ty {
+                fn encode_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decodable for $ty {
+                fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u32, u64, u128);
+
+impl Encodable for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decodable for String {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encodable> Encodable for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode_to(out),
+            Some(value) => {
+                1u8.encode_to(out);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decodable> Decodable for Option<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(input)?)),
+            tag => Err(DecodeError::InvalidTag {
+                kind: "option",
+                tag,
+            }),
+        }
+    }
+}
+
+impl<T: Encodable> Encodable for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decodable> Decodable for Vec<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(T::decode_from(input)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Non-negative integers such as targets, as length-prefixed big-endian bytes.
+impl Encodable for BigInt {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let (_, bytes) = self.to_bytes_be();
+        encode_len(bytes.len(), out);
+        out.extend_from_slice(&bytes);
+    }
+}
+
+impl Decodable for BigInt {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        Ok(BigInt::from_bytes_be(Sign::Plus, take(input, len)?))
+    }
+}
+
+impl Encodable for PublicKey {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decodable for PublicKey {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        PublicKey::from_bytes(take(input, PUBLIC_KEY_LENGTH)?)
+            .map_err(|_| DecodeError::InvalidPublicKey)
+    }
+}
+
+impl Encodable for Signature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_bytes());
+    }
+}
+
+impl Decodable for Signature {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Signature::try_from(take(input, SIGNATURE_LENGTH)?)
+            .map_err(|_| DecodeError::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primitives_round_trip() {
+        let value: (u128, String, Option<u32>, Vec<u64>) =
+            (42, "alice".to_string(), Some(7), vec![1, 2, 3]);
+
+        let mut out = vec![];
+        value.0.encode_to(&mut out);
+        value.1.encode_to(&mut out);
+        value.2.encode_to(&mut out);
+        value.3.encode_to(&mut out);
+
+        let input = &mut out.as_slice();
+        assert_eq!(u128::decode_from(input).unwrap(), value.0);
+        assert_eq!(String::decode_from(input).unwrap(), value.1);
+        assert_eq!(Option::<u32>::decode_from(input).unwrap(), value.2);
+        assert_eq!(Vec::<u64>::decode_from(input).unwrap(), value.3);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_string_layout() {
+        assert_eq!("ab".to_string().encode(), vec![2, 0, 0, 0, b'a', b'b']);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(u32::decode(&[1, 2]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(
+            String::decode(&[5, 0, 0, 0, b'a']),
+            Err(DecodeError::LengthOverflow)
+        );
+        assert_eq!(
+            Option::<u8>::decode(&[2, 0]),
+            Err(DecodeError::InvalidTag {
+                kind: "option",
+                tag: 2
+            })
+        );
+        assert_eq!(u8::decode(&[1, 2]), Err(DecodeError::TrailingBytes));
+    }
+}
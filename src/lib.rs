@@ -1,5 +1,6 @@
 extern crate core;
 
+pub mod codec;
 pub mod traits;
 pub mod types;
 pub mod utils;
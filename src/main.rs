@@ -13,6 +13,7 @@ fn main() {
         TransactionData::Transfer {
             to: account_2.clone(),
             amount: 100_000,
+            fee: 0,
         },
         Some(account_1.clone()),
     );
@@ -41,14 +42,27 @@ fn main() {
     for tx in transactions {
         block.add_transaction(tx);
     }
+    block.set_state_root(bc.compute_state_root(&block).unwrap());
 
-    mine(&mut block, bc.get_latest_target());
+    let stats = mine(&mut block, bc.get_latest_target());
 
-    println!("Mined block (genesis) with nonce: {:?}", block.nonce);
+    println!(
+        "Mined block (genesis) with nonce: {:?} ({} attempts, {:.0} H/s)",
+        block.header.nonce,
+        stats.attempts,
+        stats.hashrate()
+    );
 
     assert!(bc.append_block(block).is_ok());
 
     let transactions = vec![
+        Transaction::new(
+            TransactionData::Coinbase {
+                to: account_1.clone(),
+                amount: bc.block_subsidy(bc.len() as u128),
+            },
+            None,
+        ),
         Transaction::new(
             TransactionData::CreateAccount {
                 account_id: account_2.clone(),
@@ -64,10 +78,16 @@ fn main() {
     for tx in transactions {
         block.add_transaction(tx);
     }
+    block.set_state_root(bc.compute_state_root(&block).unwrap());
 
-    mine(&mut block, bc.get_latest_target());
+    let stats = mine(&mut block, bc.get_latest_target());
 
-    println!("Mined block with nonce: {}", block.nonce);
+    println!(
+        "Mined block with nonce: {} ({} attempts, {:.0} H/s)",
+        block.header.nonce,
+        stats.attempts,
+        stats.hashrate()
+    );
     // dbg!(bc.append_block(block.clone()));
     assert!(bc.append_block(block.clone()).is_ok());
 
@@ -86,26 +106,26 @@ fn main() {
     let mut average = 0;
 
     for block in blocktimes.into_iter().rev() {
-        if block.block_number == 0 {
-            before = block.timestamp;
+        if block.header.block_number == 0 {
+            before = block.header.timestamp;
             continue;
         }
 
         println!(
             "Block number: \t{} \n \
             Block mining time: \t{} \n \
-            Block target: \t{} \n \
+            Block target: \t{:#010x} \n \
             Block hash: \t{} \n \
             Block timestamp: {}\n",
-            block.block_number,
-            block.timestamp - before,
-            utils::to_compact_format(bc.get_target(block.block_number)),
+            block.header.block_number,
+            block.header.timestamp - before,
+            block.header.bits(),
             block.hash(),
-            block.timestamp
+            block.header.timestamp
         );
 
-        average += block.timestamp - before;
-        before = block.timestamp;
+        average += block.header.timestamp - before;
+        before = block.header.timestamp;
     }
 
     println!(
@@ -1,16 +1,39 @@
-use crate::types::{Account, AccountId, AccountType, Error, Hash};
+use crate::types::{Account, AccountId, AccountType, DecodeError, Hash, StateError};
 
 pub trait Hashable {
     fn hash(&self) -> Hash;
 }
 
+pub trait Encodable {
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub trait Decodable: Sized {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError>;
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = bytes;
+        let value = Self::decode_from(&mut input)?;
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(value)
+    }
+}
+
 pub trait WorldState {
     fn create_account(
         &mut self,
         account_id: AccountId,
         account_type: AccountType,
         public_key: ed25519_dalek::PublicKey,
-    ) -> Result<(), Error>;
-    fn get_account_by_id(&self, account_id: AccountId) -> Option<&Account>;
+    ) -> Result<(), StateError>;
+    fn get_account_by_id(&self, account_id: AccountId) -> Option<Account>;
     fn get_account_by_id_mut(&mut self, account_id: AccountId) -> Option<&mut Account>;
 }
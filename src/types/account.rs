@@ -1,15 +1,20 @@
-use crate::types::Balance;
+use crate::codec::{decode_version, encode_version};
+use crate::traits::{Decodable, Encodable};
+use crate::types::{Balance, DecodeError};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AccountType {
     User,
     Contract,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     account_type: AccountType,
     pub balance: Balance,
+    /// Number of transactions sent from this account, expected as the nonce
+    /// of its next transaction.
+    pub nonce: u128,
     pub(crate) public_key: ed25519_dalek::PublicKey,
 }
 
@@ -18,7 +23,53 @@ impl Account {
         Self {
             account_type,
             balance: 0,
+            nonce: 0,
             public_key,
         }
     }
 }
+
+impl Encodable for AccountType {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let tag: u8 = match self {
+            AccountType::User => 0,
+            AccountType::Contract => 1,
+        };
+        tag.encode_to(out);
+    }
+}
+
+impl Decodable for AccountType {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(AccountType::User),
+            1 => Ok(AccountType::Contract),
+            tag => Err(DecodeError::InvalidTag {
+                kind: "account type",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encodable for Account {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_version(out);
+        self.account_type.encode_to(out);
+        self.balance.encode_to(out);
+        self.nonce.encode_to(out);
+        self.public_key.encode_to(out);
+    }
+}
+
+impl Decodable for Account {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode_version(input)?;
+        Ok(Self {
+            account_type: AccountType::decode_from(input)?,
+            balance: Balance::decode_from(input)?,
+            nonce: u128::decode_from(input)?,
+            public_key: ed25519_dalek::PublicKey::decode_from(input)?,
+        })
+    }
+}
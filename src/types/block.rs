@@ -1,26 +1,91 @@
-use crate::traits::Hashable;
-use crate::types::{Hash, Transaction};
+use crate::codec::{decode_version, encode_version};
+use crate::traits::{Decodable, Encodable, Hashable};
+use crate::types::merkle::{merkle_proof, merkle_root, MerkleProof};
+use crate::types::{compact_to_target, DecodeError, Hash, Timestamp, Transaction};
 use blake2::digest::FixedOutput;
 use blake2::{Blake2s, Digest};
 use num::BigInt;
 
-#[derive(Default, Debug, Clone)]
-pub struct Block {
+/// Fixed-size part of a block. Proof of work is computed over the header
+/// alone, which commits to the body through `transactions_root`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct BlockHeader {
     pub nonce: u128,
     pub block_number: u128,
-    pub timestamp: u128,
-    pub(crate) hash: Option<Hash>,
+    pub timestamp: Timestamp,
+    pub(crate) bits: u32,
     pub(crate) prev_hash: Option<Hash>,
+    pub(crate) transactions_root: Hash,
+    /// Root of the state tree after the block is executed.
+    pub(crate) state_root: Hash,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct BlockBody {
     pub(crate) transactions: Vec<Transaction>,
 }
 
+#[derive(Default, Debug, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub(crate) body: BlockBody,
+    pub(crate) hash: Option<Hash>,
+}
+
+impl BlockHeader {
+    pub fn prev_hash(&self) -> Option<&Hash> {
+        self.prev_hash.as_ref()
+    }
+
+    /// Target in compact form, see `target_to_compact`.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Target decoded from `bits`. Bits that don't decode give a zero target,
+    /// which no hash meets.
+    pub fn target(&self) -> BigInt {
+        compact_to_target(self.bits).unwrap_or_default()
+    }
+
+    pub fn transactions_root(&self) -> &Hash {
+        &self.transactions_root
+    }
+
+    pub fn state_root(&self) -> &Hash {
+        &self.state_root
+    }
+
+    pub fn meets_target(&self, target: &BigInt) -> bool {
+        &BigInt::parse_bytes(self.hash().as_bytes(), 16).unwrap() < target
+    }
+}
+
+impl BlockBody {
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn transactions_root(&self) -> Hash {
+        merkle_root(&self.transaction_hashes())
+    }
+
+    fn transaction_hashes(&self) -> Vec<Hash> {
+        self.transactions.iter().map(|tx| tx.hash()).collect()
+    }
+}
+
 impl Block {
     pub fn new(prev_hash: Option<Hash>, prev_block_number: Option<u128>) -> Self {
         let mut block = Block {
-            prev_hash,
-            block_number: match prev_block_number {
-                None => 0,
-                Some(num) => num + 1,
+            header: BlockHeader {
+                prev_hash,
+                block_number: match prev_block_number {
+                    None => 0,
+                    Some(num) => num + 1,
+                },
+                transactions_root: merkle_root(&[]),
+                ..Default::default()
             },
             ..Default::default()
         };
@@ -30,22 +95,54 @@ impl Block {
         block
     }
 
+    pub fn from_parts(header: BlockHeader, body: BlockBody) -> Self {
+        let mut block = Block {
+            header,
+            body,
+            hash: None,
+        };
+        block.update_hash();
+        block
+    }
+
     pub fn set_nonce(&mut self, nonce: u128) {
-        self.nonce = nonce;
+        self.header.nonce = nonce;
+        self.update_hash();
+    }
+
+    /// Commits the block to `state_root`, see `Blockchain::compute_state_root`.
+    pub fn set_state_root(&mut self, state_root: Hash) {
+        self.header.state_root = state_root;
         self.update_hash();
     }
 
     pub fn add_transaction(&mut self, transaction: Transaction) {
-        self.transactions.push(transaction);
+        self.body.transactions.push(transaction);
+        self.header.transactions_root = self.body.transactions_root();
         self.update_hash();
     }
 
-    pub fn verify(&self, target: num::BigInt) -> bool {
-        let _hash = BigInt::parse_bytes(self.hash().as_bytes(), 16).unwrap();
+    pub fn transactions(&self) -> &[Transaction] {
+        self.body.transactions()
+    }
+
+    pub fn transactions_root(&self) -> &Hash {
+        self.header.transactions_root()
+    }
+
+    /// Size of the block in its wire encoding.
+    pub fn size(&self) -> usize {
+        self.encode().len()
+    }
+
+    pub fn merkle_proof(&self, tx_index: usize) -> Option<MerkleProof> {
+        merkle_proof(&self.body.transaction_hashes(), tx_index)
+    }
 
-        matches!(&self.hash, Some(hash) if true
-                && hash == &self.hash()
-                && _hash < target
+    pub fn verify(&self, target: num::BigInt) -> bool {
+        matches!(&self.hash, Some(hash) if hash == &self.hash()
+                && self.header.transactions_root == self.body.transactions_root()
+                && self.header.meets_target(&target)
         )
     }
 
@@ -54,28 +151,83 @@ impl Block {
     }
 }
 
-impl Hashable for Block {
+impl Hashable for BlockHeader {
     fn hash(&self) -> Hash {
         let mut hasher = Blake2s::new();
-        hasher.update(
-            format!(
-                "{:?}",
-                (self.prev_hash.clone(), self.nonce, self.block_number)
-            )
-            .as_bytes(),
-        );
-        for tx in self.transactions.iter() {
-            hasher.update(tx.hash())
-        }
+        hasher.update(self.encode());
 
         hex::encode(hasher.finalize_fixed())
     }
 }
 
+impl Hashable for Block {
+    fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_version(out);
+        self.prev_hash.encode_to(out);
+        self.block_number.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.bits.encode_to(out);
+        self.transactions_root.encode_to(out);
+        self.state_root.encode_to(out);
+        self.nonce.encode_to(out);
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode_version(input)?;
+        Ok(Self {
+            prev_hash: Option::<Hash>::decode_from(input)?,
+            block_number: u128::decode_from(input)?,
+            timestamp: Timestamp::decode_from(input)?,
+            bits: u32::decode_from(input)?,
+            transactions_root: Hash::decode_from(input)?,
+            state_root: Hash::decode_from(input)?,
+            nonce: u128::decode_from(input)?,
+        })
+    }
+}
+
+impl Encodable for BlockBody {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.transactions.encode_to(out);
+    }
+}
+
+impl Decodable for BlockBody {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            transactions: Vec::<Transaction>::decode_from(input)?,
+        })
+    }
+}
+
+impl Encodable for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.body.encode_to(out);
+    }
+}
+
+impl Decodable for Block {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Block::from_parts(
+            BlockHeader::decode_from(input)?,
+            BlockBody::decode_from(input)?,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{miner, TransactionData};
+    use crate::types::{miner, verify_merkle_proof, TransactionData};
     use crate::utils;
 
     #[test]
@@ -122,6 +274,19 @@ mod tests {
         assert_ne!(hash1, hash2);
     }
 
+    #[test]
+    fn test_hash_commits_to_timestamp_and_target() {
+        let block = Block::new(None, None);
+
+        let mut later = block.clone();
+        later.header.timestamp += 1;
+        assert_ne!(later.hash(), block.hash());
+
+        let mut easier = block.clone();
+        easier.header.bits += 1;
+        assert_ne!(easier.hash(), block.hash());
+    }
+
     #[test]
     fn test_mine() {
         let mut block = Block::new(None, None);
@@ -139,14 +304,98 @@ mod tests {
         let target = BigInt::from(5) * BigInt::from(10).pow(73);
 
         // dbg!(&target);
-        // dbg!(BigInt::parse_bytes(&block.hash().as_bytes(), 16));
+        // dbg!(BigInt::parse_bytes(block.hash().as_bytes(), 16));
 
         block.add_transaction(_tx);
 
         miner::mine(&mut block, target.clone());
 
-        assert!(BigInt::parse_bytes(&block.hash().as_bytes(), 16).unwrap() < target);
+        assert!(BigInt::parse_bytes(block.hash().as_bytes(), 16).unwrap() < target);
+
+        // dbg!(block.header.nonce);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut block = Block::new(Some("00ff".to_string()), Some(4));
+
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account_alice,
+                public_key: keypair_alice.public,
+            },
+            None,
+        ));
+        block.header.timestamp = 1_000;
+        block.set_nonce(7);
+
+        let decoded = Block::decode(&block.encode()).unwrap();
+
+        assert_eq!(decoded.hash, block.hash);
+        assert_eq!(decoded.header, block.header);
+        assert_eq!(decoded.body, block.body);
+        assert!(Block::decode(&block.encode()[1..]).is_err());
+        assert_eq!(
+            BlockHeader::decode(&block.header.encode()).unwrap(),
+            block.header
+        );
+    }
+
+    #[test]
+    fn test_verify_body_against_header() {
+        let target = BigInt::from(2).pow(256);
+        let mut block = Block::new(None, None);
+        let mut other = Block::new(None, None);
+
+        for block in [&mut block, &mut other] {
+            let (account_id, keypair) = utils::generate_account_id();
+            block.add_transaction(Transaction::new(
+                TransactionData::CreateAccount {
+                    account_id,
+                    public_key: keypair.public,
+                },
+                None,
+            ));
+        }
+
+        assert!(block.verify(target.clone()));
+
+        let swapped = Block::from_parts(block.header.clone(), other.body.clone());
+
+        assert_eq!(swapped.hash(), block.hash());
+        assert!(!swapped.verify(target));
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let mut block = Block::new(None, None);
+
+        for _ in 0..3 {
+            let (account_id, keypair) = utils::generate_account_id();
+            block.add_transaction(Transaction::new(
+                TransactionData::CreateAccount {
+                    account_id,
+                    public_key: keypair.public,
+                },
+                None,
+            ));
+        }
 
-        // dbg!(block.nonce);
+        let tx_hash = block.transactions()[1].hash();
+        let proof = block.merkle_proof(1).unwrap();
+
+        assert!(verify_merkle_proof(
+            block.transactions_root(),
+            &tx_hash,
+            &proof
+        ));
+        assert!(!verify_merkle_proof(
+            block.transactions_root(),
+            &block.transactions()[0].hash(),
+            &proof
+        ));
+        assert!(block.merkle_proof(3).is_none());
     }
 }
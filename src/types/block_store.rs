@@ -0,0 +1,401 @@
+//! Storage backends for the blocks a `Blockchain` accepted.
+//!
+//! Blocks are kept in the order they were accepted, side branches included,
+//! so replaying them through `Blockchain::append_block` rebuilds the block
+//! tree, the canonical chain and the world state.
+
+use crate::traits::{Decodable, Encodable};
+use crate::types::record::{encode_record, read_record};
+use crate::types::{Block, StoreError};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+const DATA_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "blocks.idx";
+const OFFSET_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+pub trait BlockStore: Debug + Send + Sync {
+    /// Persists `block` after the blocks already stored.
+    fn append(&mut self, block: &Block) -> Result<(), StoreError>;
+
+    /// Number of stored blocks.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `index`-th stored block, in the order they were appended.
+    fn get(&self, index: usize) -> Result<Option<Block>, StoreError>;
+
+    /// Every stored block, in the order they were appended.
+    fn blocks(&self) -> Result<Vec<Block>, StoreError> {
+        (0..self.len())
+            .map(|index| Ok(self.get(index)?.expect("index is below len")))
+            .collect()
+    }
+}
+
+/// Keeps the blocks in memory, for tests and throwaway chains.
+#[derive(Debug, Default)]
+pub struct MemoryBlockStore {
+    blocks: Vec<Block>,
+}
+
+impl MemoryBlockStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn append(&mut self, block: &Block) -> Result<(), StoreError> {
+        self.blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Block>, StoreError> {
+        Ok(self.blocks.get(index).cloned())
+    }
+}
+
+/// Append-only block file with an index of record offsets.
+///
+/// Each record in `blocks.dat` is the encoded block prefixed with its length
+/// and followed by a checksum. `blocks.idx` holds the offset of every record
+/// as a little-endian u64. A record is written and synced before its offset,
+/// so after a crash the index can only lag behind the data file, and the
+/// data file can only end in a torn record. Both are repaired on `open`.
+#[derive(Debug)]
+pub struct FileBlockStore {
+    /// Locked since reads move the shared file cursor.
+    data: Mutex<File>,
+    index: File,
+    offsets: Vec<u64>,
+    end: u64,
+    truncated: u64,
+}
+
+impl FileBlockStore {
+    /// Opens the store in `dir`, creating it if needed. Records missing from
+    /// the index are indexed again and a torn record at the end of the data
+    /// file is cut off.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let options = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .clone();
+        let data = options.open(dir.join(DATA_FILE))?;
+        let mut index = options.open(dir.join(INDEX_FILE))?;
+        let data_len = data.metadata()?.len();
+
+        let mut bytes = vec![];
+        index.read_to_end(&mut bytes)?;
+        let mut offsets: Vec<u64> = vec![];
+        for chunk in bytes.chunks_exact(OFFSET_SIZE as usize) {
+            let offset = u64::from_le_bytes(chunk.try_into().unwrap());
+            let expected_after = offsets.last().is_none_or(|last| offset > *last);
+            if !expected_after || offset >= data_len {
+                break;
+            }
+            offsets.push(offset);
+        }
+
+        // Only the last indexed record needs checking, the ones before it
+        // are bounded by the offsets that follow them.
+        let mut end = 0;
+        while let Some(offset) = offsets.last() {
+            match read_record(&data, *offset)? {
+                Some((_, next)) => {
+                    end = next;
+                    break;
+                }
+                None => {
+                    offsets.pop();
+                }
+            }
+        }
+        if offsets.first().is_some_and(|offset| *offset != 0) {
+            offsets.clear();
+            end = 0;
+        }
+        let indexed = offsets.len();
+
+        while let Some((_, next)) = read_record(&data, end)? {
+            offsets.push(end);
+            end = next;
+        }
+
+        data.set_len(end)?;
+        data.sync_data()?;
+        index.set_len(indexed as u64 * OFFSET_SIZE)?;
+        index.seek(SeekFrom::End(0))?;
+        for offset in &offsets[indexed..] {
+            index.write_all(&offset.to_le_bytes())?;
+        }
+        index.sync_data()?;
+
+        Ok(Self {
+            data: Mutex::new(data),
+            index,
+            offsets,
+            end,
+            truncated: data_len - end,
+        })
+    }
+
+    /// Bytes cut off the end of the data file by `open`.
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn append(&mut self, block: &Block) -> Result<(), StoreError> {
+        let record = encode_record(&block.encode());
+
+        let data = self.data.get_mut().unwrap();
+        data.seek(SeekFrom::Start(self.end))?;
+        data.write_all(&record)?;
+        data.sync_data()?;
+
+        self.index.write_all(&self.end.to_le_bytes())?;
+        self.index.sync_data()?;
+
+        self.offsets.push(self.end);
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Block>, StoreError> {
+        let Some(offset) = self.offsets.get(index) else {
+            return Ok(None);
+        };
+        match read_record(&self.data.lock().unwrap(), *offset)? {
+            Some((payload, _)) => Ok(Some(Block::decode(&payload)?)),
+            None => Err(StoreError::Corrupt { offset: *offset }),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::traits::Hashable;
+    use crate::types::record::HEADER_SIZE;
+    use crate::types::{Transaction, TransactionData};
+    use crate::utils;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Fresh directory under the system temp dir, removed on drop.
+    pub(crate) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            Self(std::env::temp_dir().join(format!(
+                "blockchain_workshop_{}_{}_{}",
+                name,
+                std::process::id(),
+                nanos
+            )))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn block(prev: Option<&Block>) -> Block {
+        let mut block = Block::new(
+            prev.map(|prev| prev.hash()),
+            prev.map(|prev| prev.header.block_number),
+        );
+        let (account_id, keypair) = utils::generate_account_id();
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id,
+                public_key: keypair.public,
+            },
+            None,
+        ));
+        block
+    }
+
+    fn hashes(store: &dyn BlockStore) -> Vec<String> {
+        store
+            .blocks()
+            .unwrap()
+            .iter()
+            .map(|block| block.hash())
+            .collect()
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let dir = TempDir::new("append_and_reopen");
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        assert!(store.is_empty());
+
+        let genesis = block(None);
+        let second = block(Some(&genesis));
+        store.append(&genesis).unwrap();
+        store.append(&second).unwrap();
+        assert_eq!(store.get(1).unwrap().unwrap().hash(), second.hash());
+        assert_eq!(store.get(2).unwrap().map(|block| block.hash()), None);
+        drop(store);
+
+        let store = FileBlockStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 0);
+        assert_eq!(hashes(&store), vec![genesis.hash(), second.hash()]);
+    }
+
+    #[test]
+    fn test_torn_write() {
+        let dir = TempDir::new("torn_write");
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        let genesis = block(None);
+        let second = block(Some(&genesis));
+        store.append(&genesis).unwrap();
+        store.append(&second).unwrap();
+        drop(store);
+
+        let data_path = dir.0.join(DATA_FILE);
+        let intact_len = fs::metadata(&data_path).unwrap().len();
+
+        // A record cut short by a crash, never indexed.
+        let mut data = OpenOptions::new().append(true).open(&data_path).unwrap();
+        data.write_all(&1_000u32.to_le_bytes()).unwrap();
+        data.write_all(&[1, 2, 3]).unwrap();
+        drop(data);
+
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 7);
+        assert_eq!(fs::metadata(&data_path).unwrap().len(), intact_len);
+        assert_eq!(hashes(&store), vec![genesis.hash(), second.hash()]);
+
+        let third = block(Some(&second));
+        store.append(&third).unwrap();
+        drop(store);
+
+        let store = FileBlockStore::open(&dir.0).unwrap();
+        assert_eq!(
+            hashes(&store),
+            vec![genesis.hash(), second.hash(), third.hash()]
+        );
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        let dir = TempDir::new("rebuild_index");
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        let genesis = block(None);
+        let second = block(Some(&genesis));
+        store.append(&genesis).unwrap();
+        store.append(&second).unwrap();
+        drop(store);
+
+        // The data was synced but the crash hit before the index entry, and
+        // left half of the previous entry.
+        let index_path = dir.0.join(INDEX_FILE);
+        let index = OpenOptions::new().write(true).open(&index_path).unwrap();
+        index.set_len(OFFSET_SIZE + 3).unwrap();
+        drop(index);
+
+        let store = FileBlockStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 0);
+        assert_eq!(hashes(&store), vec![genesis.hash(), second.hash()]);
+        assert_eq!(fs::metadata(&index_path).unwrap().len(), 2 * OFFSET_SIZE);
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        let dir = TempDir::new("corrupt_record");
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        let genesis = block(None);
+        let second = block(Some(&genesis));
+        store.append(&genesis).unwrap();
+        store.append(&second).unwrap();
+        let data_path = dir.0.join(DATA_FILE);
+        let len = fs::metadata(&data_path).unwrap().len();
+
+        // Flip a byte of the payload, so the record fails its checksum.
+        let mut data = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&data_path)
+            .unwrap();
+        data.seek(SeekFrom::Start(HEADER_SIZE + 1)).unwrap();
+        data.write_all(&[0xff]).unwrap();
+        drop(data);
+
+        assert!(matches!(
+            store.get(0),
+            Err(StoreError::Corrupt { offset: 0 })
+        ));
+        drop(store);
+
+        // Only the last record is checked on open, the damaged one is still
+        // reported when read and nothing behind it is cut off.
+        let store = FileBlockStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 0);
+        assert!(store.get(0).is_err());
+        assert_eq!(store.get(1).unwrap().unwrap().hash(), second.hash());
+        drop(store);
+
+        // Rebuilding the index runs into it and fails rather than truncate.
+        fs::write(dir.0.join(INDEX_FILE), []).unwrap();
+        assert!(matches!(
+            FileBlockStore::open(&dir.0),
+            Err(StoreError::Corrupt { offset: 0 })
+        ));
+        assert_eq!(fs::metadata(&data_path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let dir = TempDir::new("corrupt_length");
+        let mut store = FileBlockStore::open(&dir.0).unwrap();
+        let genesis = block(None);
+        let second = block(Some(&genesis));
+        store.append(&genesis).unwrap();
+        store.append(&second).unwrap();
+        drop(store);
+        let data_path = dir.0.join(DATA_FILE);
+        let len = fs::metadata(&data_path).unwrap().len();
+
+        // A length running past the end of the file, which a torn record
+        // would have too, but which fails its checksum.
+        let mut data = OpenOptions::new().write(true).open(&data_path).unwrap();
+        data.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        drop(data);
+        fs::write(dir.0.join(INDEX_FILE), []).unwrap();
+
+        assert!(matches!(
+            FileBlockStore::open(&dir.0),
+            Err(StoreError::Corrupt { offset: 0 })
+        ));
+        assert_eq!(fs::metadata(&data_path).unwrap().len(), len);
+    }
+}
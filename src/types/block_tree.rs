@@ -0,0 +1,240 @@
+use crate::traits::Hashable;
+use crate::types::{Block, BlockError, Hash};
+use num::BigInt;
+use std::collections::HashMap;
+
+/// Expected number of hashes needed to find a block below `target`.
+pub fn block_work(target: &BigInt) -> BigInt {
+    BigInt::from(2).pow(256) / (target + 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTreeEntry {
+    pub block: Block,
+    pub height: u128,
+    /// Sum of the work of this block and all its ancestors.
+    pub cumulative_work: BigInt,
+    /// Target the children of this block have to meet.
+    pub next_target: BigInt,
+}
+
+/// Every known block indexed by hash, including blocks on side branches.
+/// A block can only be inserted once its parent is known.
+#[derive(Default, Debug)]
+pub struct BlockTree {
+    entries: HashMap<Hash, BlockTreeEntry>,
+    children: HashMap<Hash, Vec<Hash>>,
+    genesis: Option<Hash>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&BlockTreeEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn genesis(&self) -> Option<&Hash> {
+        self.genesis.as_ref()
+    }
+
+    pub fn insert(
+        &mut self,
+        block: Block,
+        work: BigInt,
+        next_target: BigInt,
+    ) -> Result<&BlockTreeEntry, BlockError> {
+        let hash = block.hash();
+        if self.contains(&hash) {
+            return Err(BlockError::AlreadyKnown(hash));
+        }
+
+        let (height, cumulative_work) = match block.header.prev_hash() {
+            None if self.genesis.is_some() => {
+                return Err(BlockError::GenesisAlreadyExists);
+            }
+            None => {
+                self.genesis = Some(hash.clone());
+                (0, work)
+            }
+            Some(parent_hash) => {
+                let parent = match self.entries.get(parent_hash) {
+                    Some(parent) => parent,
+                    None => {
+                        return Err(BlockError::UnknownParent {
+                            block_number: block.header.block_number,
+                        })
+                    }
+                };
+                self.children
+                    .entry(parent_hash.clone())
+                    .or_default()
+                    .push(hash.clone());
+                (parent.height + 1, &parent.cumulative_work + work)
+            }
+        };
+
+        Ok(self.entries.entry(hash).or_insert(BlockTreeEntry {
+            block,
+            height,
+            cumulative_work,
+            next_target,
+        }))
+    }
+
+    /// Entry with the most cumulative work.
+    pub fn heaviest(&self) -> Option<&BlockTreeEntry> {
+        self.entries
+            .values()
+            .max_by(|a, b| a.cumulative_work.cmp(&b.cumulative_work))
+    }
+
+    /// Blocks from genesis up to and including `hash`.
+    pub fn path_from_genesis(&self, hash: &Hash) -> Vec<&Block> {
+        let mut path: Vec<&Block> = self.ancestors(hash).collect();
+        path.reverse();
+        path
+    }
+
+    /// Blocks from `hash` back to genesis.
+    pub fn ancestors<'a>(&'a self, hash: &Hash) -> impl Iterator<Item = &'a Block> + 'a {
+        let mut next = self.entries.get(hash);
+        std::iter::from_fn(move || {
+            let entry = next?;
+            next = entry
+                .block
+                .header
+                .prev_hash()
+                .and_then(|parent| self.entries.get(parent));
+            Some(&entry.block)
+        })
+    }
+
+    /// Last common ancestor of `a` and `b`.
+    pub fn fork_point(&self, a: &Hash, b: &Hash) -> Option<Hash> {
+        let mut a = self.entries.get(a)?;
+        let mut b = self.entries.get(b)?;
+
+        while a.block.hash() != b.block.hash() {
+            let higher = if a.height >= b.height { &mut a } else { &mut b };
+            *higher = self.entries.get(higher.block.header.prev_hash()?)?;
+        }
+
+        Some(a.block.hash())
+    }
+
+    /// Removes `hash` and all of its descendants.
+    pub fn remove_subtree(&mut self, hash: &Hash) {
+        let Some(entry) = self.entries.remove(hash) else {
+            return;
+        };
+
+        match entry.block.header.prev_hash() {
+            Some(parent) => {
+                if let Some(siblings) = self.children.get_mut(parent) {
+                    siblings.retain(|child| child != hash);
+                }
+            }
+            None => self.genesis = None,
+        }
+
+        for child in self.children.remove(hash).unwrap_or_default() {
+            self.remove_subtree(&child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Transaction, TransactionData};
+    use crate::utils;
+
+    fn block_on(parent: Option<&Block>) -> Block {
+        let mut block = match parent {
+            None => Block::new(None, None),
+            Some(parent) => Block::new(Some(parent.hash()), Some(parent.header.block_number)),
+        };
+        let (account_id, keypair) = utils::generate_account_id();
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id,
+                public_key: keypair.public,
+            },
+            None,
+        ));
+        block
+    }
+
+    #[test]
+    fn test_insert_and_paths() {
+        let mut tree = BlockTree::new();
+
+        let genesis = block_on(None);
+        let a1 = block_on(Some(&genesis));
+        let b1 = block_on(Some(&genesis));
+        let b2 = block_on(Some(&b1));
+
+        for block in [&genesis, &a1, &b1, &b2] {
+            assert!(tree
+                .insert(block.clone(), BigInt::from(2), BigInt::from(1))
+                .is_ok());
+        }
+
+        assert!(tree
+            .insert(b2.clone(), BigInt::from(2), BigInt::from(1))
+            .is_err());
+        assert!(tree
+            .insert(block_on(None), BigInt::from(2), BigInt::from(1))
+            .is_err());
+        assert!(tree
+            .insert(
+                block_on(Some(&block_on(None))),
+                BigInt::from(2),
+                BigInt::from(1)
+            )
+            .is_err());
+
+        let entry = tree.get(&b2.hash()).unwrap();
+        assert_eq!(entry.height, 2);
+        assert_eq!(entry.cumulative_work, BigInt::from(6));
+
+        let path: Vec<Hash> = tree
+            .path_from_genesis(&b2.hash())
+            .iter()
+            .map(|block| block.hash())
+            .collect();
+        assert_eq!(path, vec![genesis.hash(), b1.hash(), b2.hash()]);
+
+        assert_eq!(
+            tree.fork_point(&a1.hash(), &b2.hash()),
+            Some(genesis.hash())
+        );
+        assert_eq!(tree.fork_point(&b1.hash(), &b2.hash()), Some(b1.hash()));
+
+        tree.remove_subtree(&b1.hash());
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.contains(&b2.hash()));
+        assert!(tree.contains(&a1.hash()));
+    }
+
+    #[test]
+    fn test_block_work() {
+        assert_eq!(block_work(&(BigInt::from(2).pow(256) - 1)), BigInt::from(1));
+        assert_eq!(block_work(&(BigInt::from(2).pow(255) - 1)), BigInt::from(2));
+    }
+}
@@ -1,19 +1,44 @@
-use crate::traits::{Hashable, WorldState};
-use crate::types::{Account, AccountId, AccountType, Block, Chain, Error, Hash, Transaction};
-use num::{BigInt, FromPrimitive};
-use std::cmp::{max, min};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-
-const MAX_TARGET_CHANGE: i32 = 10; // x0.10 or x10
-const EXPECTED_TIME: i32 = 1000 * 60 * 1; // 1 min
-const X: u128 = 2; // Target will generate after X blocks
-
-#[derive(Default, Debug)]
+use crate::traits::{Decodable, Encodable, Hashable, WorldState};
+use crate::types::state::StateOverlay;
+use crate::types::{
+    block_work, round_target, target_to_compact, Account, AccountChange, AccountId, AccountProof,
+    AccountType, Balance, Block, BlockError, BlockStore, BlockTree, BlockUndo, Chain, Clock,
+    ConsensusParams, Error, Hash, JournaledState, Mempool, MempoolError, Reorg, RewardSchedule,
+    StateError, StateStore, StoreError, SystemClock, Timestamp, Transaction, TransactionData,
+};
+use num::BigInt;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// State store key of the hash of the block the stored state is at.
+const TIP_KEY: &[u8] = b"tip";
+/// Prefix of the state store keys of the undo records, by block hash.
+const UNDO_PREFIX: &[u8] = b"undo/";
+
+#[derive(Debug)]
 pub struct Blockchain {
     pub blocks: Chain<Block>,
-    accounts: HashMap<AccountId, Account>,
-    transaction_pool: Vec<Transaction>,
+    state: JournaledState,
+    tree: BlockTree,
+    transaction_pool: Mempool,
+    params: ConsensusParams,
+    clock: Arc<dyn Clock>,
+    /// Where accepted blocks are persisted, if anywhere.
+    store: Option<Box<dyn BlockStore>>,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self {
+            blocks: Default::default(),
+            state: Default::default(),
+            tree: Default::default(),
+            transaction_pool: Default::default(),
+            params: Default::default(),
+            clock: Arc::new(SystemClock),
+            store: None,
+        }
+    }
 }
 
 impl WorldState for Blockchain {
@@ -22,155 +47,878 @@ impl WorldState for Blockchain {
         account_id: AccountId,
         account_type: AccountType,
         public_key: ed25519_dalek::PublicKey,
-    ) -> Result<(), Error> {
-        match self.accounts.entry(account_id.clone()) {
-            Entry::Occupied(_) => Err(format!("AccountId already exist")),
-            Entry::Vacant(v) => {
-                let account = Account::new(account_type, public_key);
-                v.insert(account);
-                Ok(())
-            }
-        }
+    ) -> Result<(), StateError> {
+        self.state
+            .create_account(account_id, account_type, public_key)
     }
 
-    fn get_account_by_id(&self, account_id: AccountId) -> Option<&Account> {
-        self.accounts.get(&account_id)
+    fn get_account_by_id(&self, account_id: AccountId) -> Option<Account> {
+        self.state.get_account_by_id(account_id)
     }
 
     fn get_account_by_id_mut(&mut self, account_id: AccountId) -> Option<&mut Account> {
-        self.accounts.get_mut(&account_id)
+        self.state.get_account_by_id_mut(account_id)
     }
 }
 
 impl Blockchain {
     pub fn new() -> Self {
-        let nbc = Self {
+        Default::default()
+    }
+
+    pub fn new_with_params(params: ConsensusParams) -> Self {
+        Self {
+            params,
             ..Default::default()
-        };
-        nbc
+        }
+    }
+
+    /// Replaces the clock used for mining, timestamp validation and mempool
+    /// expiry.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Keeps the world state, and the undo records of the canonical blocks,
+    /// in `store` instead of memory. A store that already holds a state can
+    /// only be reopened together with the block store it was written
+    /// alongside, see `with_store`, which has to come after this.
+    pub fn with_state_store(mut self, store: Box<dyn StateStore>) -> Self {
+        self.state = JournaledState::with_store(store);
+        self
+    }
+
+    /// Replays the blocks in `store` onto this chain, which should still be
+    /// empty, and persists every block accepted from now on. Replayed blocks
+    /// are validated again, so a store written with other consensus params
+    /// is rejected. If the state store already holds the state the blocks
+    /// led to, they are not executed again.
+    pub fn with_store(mut self, store: Box<dyn BlockStore>) -> Result<Self, Error> {
+        let blocks = store.blocks()?;
+        match self.state_tip()? {
+            Some(tip) => self.restore(blocks, tip)?,
+            None => {
+                for block in blocks {
+                    self.accept_block(block)?;
+                }
+            }
+        }
+        self.store = Some(store);
+        Ok(self)
+    }
+
+    /// Rebuilds the block tree from `blocks` and makes the branch up to
+    /// `tip`, the block the stored state is at, canonical without executing
+    /// it.
+    fn restore(&mut self, blocks: Vec<Block>, tip: Hash) -> Result<(), Error> {
+        for block in blocks {
+            let target = self.check_block(&block)?;
+            let next_target = self.compute_next_target(&block);
+            self.tree.insert(block, block_work(&target), next_target)?;
+        }
+
+        // The state is written before the block store, so a crash in between
+        // leaves the state at a block that was never stored.
+        let mut tip = Some(tip);
+        while let Some(hash) = tip.clone().filter(|hash| !self.tree.contains(hash)) {
+            let (parent, undo) = self
+                .load_undo(&hash)?
+                .ok_or_else(|| BlockError::UnknownBlock(hash.clone()))?;
+            undo.revert(&mut self.state);
+            self.state
+                .flush(vec![(undo_key(&hash), None), tip_write(parent.as_ref())])?;
+            tip = parent;
+        }
+        for block in tip.iter().flat_map(|tip| self.tree.path_from_genesis(tip)) {
+            self.blocks.append(block.clone());
+        }
+
+        // Only a state older than the block store leaves a heavier branch.
+        let tip_work = self.tip_cumulative_work();
+        let heaviest = self
+            .tree
+            .heaviest()
+            .filter(|entry| entry.cumulative_work > tip_work)
+            .map(|entry| entry.block.hash());
+        if let Some(heaviest) = heaviest {
+            if self.blocks.is_empty() {
+                let path: Vec<Block> = self
+                    .tree
+                    .path_from_genesis(&heaviest)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                for block in path {
+                    self.connect_block(block)?;
+                }
+            } else {
+                self.reorg_to(&heaviest)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &ConsensusParams {
+        &self.params
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
     }
 
     pub fn len(&self) -> usize {
         self.blocks.len()
     }
 
-    pub fn append_block(&mut self, block: Block) -> Result<(), Error> {
-        if !block.verify(self.get_latest_target()) {
-            return Err("Block has invalid hash".to_string());
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Adds `block` to the block tree. A block extending the current tip is
+    /// executed right away; a block on another branch is only stored, unless
+    /// its branch now has more cumulative work than the current one, in which
+    /// case the chain is reorganised onto it and the transactions that fell
+    /// out of the canonical chain go back to the pool. Accepted blocks are
+    /// written to the block store; if that fails the block is taken back
+    /// out of the tree and the chain returns to its previous tip.
+    pub fn append_block(&mut self, block: Block) -> Result<(), BlockError> {
+        let stored = self.store.is_some().then(|| block.clone());
+        let previous_tip = self.get_last_block_hash();
+        self.accept_block(block)?;
+
+        if let (Some(store), Some(block)) = (&mut self.store, stored) {
+            if let Err(error) = store.append(&block) {
+                self.unaccept_block(&block.hash(), previous_tip)?;
+                return Err(BlockError::Store {
+                    block_number: block.header.block_number,
+                    error,
+                });
+            }
         }
-        let is_genesis = self.blocks.len() == 0;
+        Ok(())
+    }
 
-        if block.transactions.len() == 0 {
-            return Err("Block has 0 transactions.".to_string());
+    /// Undoes `accept_block` for the block `hash`, accepted while the tip was
+    /// `previous_tip`.
+    fn unaccept_block(
+        &mut self,
+        hash: &Hash,
+        previous_tip: Option<Hash>,
+    ) -> Result<(), BlockError> {
+        match previous_tip {
+            Some(previous_tip) if self.get_last_block_hash() != Some(previous_tip.clone()) => {
+                self.reorg_to(&previous_tip)?;
+            }
+            None => while self.disconnect_block()?.is_some() {},
+            Some(_) => {}
         }
+        self.tree.remove_subtree(hash);
+        Ok(())
+    }
+
+    fn accept_block(&mut self, block: Block) -> Result<(), BlockError> {
+        let target = self.check_block(&block)?;
 
-        let account_backup = self.accounts.clone();
-        for tx in &block.transactions {
-            let res = tx.execute(self, is_genesis);
-            if let Err(error) = res {
-                self.accounts = account_backup;
-                return Err(format!("Error during tx execution: {}", error));
+        if block.header.prev_hash().cloned() != self.get_last_block_hash() {
+            let hash = block.hash();
+            let tip_work = self.tip_cumulative_work();
+            let next_target = self.compute_next_target(&block);
+            let entry = self.tree.insert(block, block_work(&target), next_target)?;
+            if entry.cumulative_work > tip_work {
+                self.reorg_to(&hash)?;
             }
+            return Ok(());
         }
 
-        if !block.verify(self.get_latest_target()) {
-            return Err("Block has invalid hash".to_string());
+        if self.tree.contains(&block.hash()) {
+            return Err(BlockError::AlreadyKnown(block.hash()));
         }
+
         // DONE Task 3: Append block only if block.hash < target
         // Adjust difficulty of target each block generation (epoch)
+        self.connect_block(block.clone())?;
+        let next_target = self.compute_next_target(&block);
+        self.tree.insert(block, block_work(&target), next_target)?;
 
-        self.blocks.append(block);
+        self.remove_stale_transactions();
+
+        Ok(())
+    }
+
+    /// Checks everything about `block` that doesn't need executing it, and
+    /// returns the target it had to meet.
+    fn check_block(&self, block: &Block) -> Result<BigInt, BlockError> {
+        let block_number = block.header.block_number;
+        let parent = match block.header.prev_hash() {
+            None if !self.tree.is_empty() => return Err(BlockError::GenesisAlreadyExists),
+            None => None,
+            Some(parent_hash) => match self.tree.get(parent_hash) {
+                Some(parent) => Some(parent),
+                None => return Err(BlockError::UnknownParent { block_number }),
+            },
+        };
+
+        let expected = parent.map_or(0, |parent| parent.height + 1);
+        if block_number != expected {
+            return Err(BlockError::InvalidBlockNumber {
+                block_number,
+                expected,
+            });
+        }
+
+        // Timestamps feed the retarget, so they may neither fall behind the
+        // median of the last blocks nor run too far ahead of local time.
+        let timestamp = block.header.timestamp;
+        if let Some(parent_hash) = block.header.prev_hash() {
+            let min = self.median_time_past(parent_hash);
+            if timestamp < min {
+                return Err(BlockError::TimestampTooOld {
+                    block_number,
+                    timestamp,
+                    min,
+                });
+            }
+        }
+        let max = self.clock.now() + self.params.max_future_drift;
+        if timestamp > max {
+            return Err(BlockError::TimestampTooNew {
+                block_number,
+                timestamp,
+                max,
+            });
+        }
+
+        let target = self.get_next_target(block.header.prev_hash());
+        if block.header.bits() != target_to_compact(&target) {
+            return Err(BlockError::InvalidTarget { block_number });
+        }
+        if !block.verify(target.clone()) {
+            return Err(BlockError::InvalidHash { block_number });
+        }
+
+        if block.transactions().is_empty() {
+            return Err(BlockError::EmptyBlock { block_number });
+        }
 
+        let size = block.size();
+        if size > self.params.max_block_size {
+            return Err(BlockError::TooLarge {
+                block_number,
+                size,
+                max: self.params.max_block_size,
+            });
+        }
+
+        Ok(target)
+    }
+
+    /// Executes the transactions of `block` on `state` and returns the record
+    /// needed to revert it. The state is left untouched if any of them fails.
+    fn execute_block(
+        state: &mut JournaledState,
+        reward_schedule: &RewardSchedule,
+        block: &Block,
+    ) -> Result<BlockUndo, BlockError> {
+        state
+            .preload(block.transactions().iter().flat_map(|tx| tx.accounts()))
+            .map_err(|error| BlockError::Store {
+                block_number: block.header.block_number,
+                error,
+            })?;
+        state.checkpoint();
+        match Self::execute_transactions(state, reward_schedule, block) {
+            Ok(()) => Ok(BlockUndo::new(state.commit())),
+            Err(error) => {
+                state.revert();
+                Err(error)
+            }
+        }
+    }
+
+    /// Executes the transactions of `block` on `state`, stopping at the
+    /// first one that fails.
+    fn execute_transactions<S: WorldState>(
+        state: &mut S,
+        reward_schedule: &RewardSchedule,
+        block: &Block,
+    ) -> Result<(), BlockError> {
+        let block_number = block.header.block_number;
+        let is_genesis = block_number == 0;
+
+        let (coinbase, transactions) = match block.transactions().split_first() {
+            Some((first, rest)) if first.is_coinbase() => (Some(first), rest),
+            _ => (None, block.transactions()),
+        };
+
+        if coinbase.is_none() && !is_genesis {
+            return Err(BlockError::MissingCoinbase { block_number });
+        }
+
+        // Index of the first non-coinbase transaction within the block.
+        let offset = block.transactions().len() - transactions.len();
+
+        if let Some(position) = transactions.iter().position(|tx| tx.is_coinbase()) {
+            return Err(BlockError::MisplacedCoinbase {
+                block_number,
+                tx_index: offset + position,
+            });
+        }
+
+        let mut fees: Balance = 0;
+        for (position, tx) in transactions.iter().enumerate() {
+            tx.execute(state, is_genesis)
+                .map_err(|error| BlockError::Transaction {
+                    block_number,
+                    tx_index: offset + position,
+                    error,
+                })?;
+            fees = fees
+                .checked_add(tx.fee())
+                .ok_or(BlockError::RewardOverflow { block_number })?;
+        }
+
+        // The coinbase is credited last: its amount depends on the fees of
+        // the whole block and it may pay an account created in this block.
+        if let Some(coinbase) = coinbase {
+            let reward = reward_schedule
+                .subsidy(block_number)
+                .checked_add(fees)
+                .ok_or(BlockError::RewardOverflow { block_number })?;
+            if let TransactionData::Coinbase { amount, .. } = &coinbase.data {
+                if *amount > reward {
+                    return Err(BlockError::CoinbaseExceedsReward {
+                        block_number,
+                        amount: *amount,
+                        reward,
+                    });
+                }
+            }
+            coinbase
+                .execute(state, is_genesis)
+                .map_err(|error| BlockError::Transaction {
+                    block_number,
+                    tx_index: 0,
+                    error,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes `block` on top of the canonical tip, checks the state root it
+    /// commits to and appends it. The new state is written to the state
+    /// store together with the undo record of the block.
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockError> {
+        let block_number = block.header.block_number;
+        let undo = Self::execute_block(&mut self.state, &self.params.reward_schedule, &block)?;
+
+        let state_root = self.state.state_root();
+        if state_root.as_ref() != Ok(block.header.state_root()) {
+            undo.revert(&mut self.state);
+            return Err(match state_root {
+                Ok(_) => BlockError::InvalidStateRoot { block_number },
+                Err(error) => BlockError::Store {
+                    block_number,
+                    error,
+                },
+            });
+        }
+
+        let hash = block.hash();
+        let mut record = block.header.prev_hash.encode();
+        undo.encode_to(&mut record);
+        let batch = vec![(undo_key(&hash), Some(record)), tip_write(Some(&hash))];
+        if let Err(error) = self.state.flush(batch) {
+            undo.revert(&mut self.state);
+            return Err(BlockError::Store {
+                block_number,
+                error,
+            });
+        }
+
+        self.blocks.append(block);
         Ok(())
     }
 
-    pub fn validate(&self) -> Result<(), Error> {
+    /// Removes the canonical tip and reverts its changes to the state.
+    fn disconnect_block(&mut self) -> Result<Option<Block>, BlockError> {
+        let Some(block) = self.blocks.head() else {
+            return Ok(None);
+        };
+        let hash = block.hash();
+        let block_number = block.header.block_number;
+        let store_error = |error| BlockError::Store {
+            block_number,
+            error,
+        };
+
+        let (parent, undo) = self.canonical_undo(block)?;
+        let redo = BlockUndo::new(
+            undo.accounts()
+                .iter()
+                .map(|(account_id, _)| Ok((account_id.clone(), self.state.account(account_id)?)))
+                .collect::<Result<_, _>>()
+                .map_err(store_error)?,
+        );
+        undo.revert(&mut self.state);
+        if let Err(error) = self
+            .state
+            .flush(vec![(undo_key(&hash), None), tip_write(parent.as_ref())])
+        {
+            redo.revert(&mut self.state);
+            return Err(store_error(error));
+        }
+
+        Ok(self.blocks.pop())
+    }
+
+    /// State root `block` has to commit to: the root of the state after
+    /// executing it on top of its parent, which may be on any known branch.
+    /// The state at the parent is recovered from the undo records of the
+    /// canonical blocks above the fork point and the blocks of the parent's
+    /// branch, without touching the live state.
+    pub fn compute_state_root(&self, block: &Block) -> Result<Hash, BlockError> {
+        let block_number = block.header.block_number;
+        let fork_point = match block.header.prev_hash() {
+            None => None,
+            Some(parent) => Some(
+                self.get_last_block_hash()
+                    .and_then(|tip| self.tree.fork_point(&tip, parent))
+                    .ok_or(BlockError::UnknownParent { block_number })?,
+            ),
+        };
+
+        let mut state = StateOverlay::new(&self.state);
+        // Undo records hold the values from before their block, so going down
+        // from the tip leaves the values as of the fork point.
+        for canonical in self
+            .blocks
+            .iter()
+            .take_while(|canonical| Some(canonical.hash()) != fork_point)
+        {
+            let (_, undo) = self.canonical_undo(canonical)?;
+            for (account_id, account) in undo.accounts() {
+                state.set_account(account_id.clone(), account.clone());
+            }
+        }
+
+        let mut branch: Vec<&Block> = block
+            .header
+            .prev_hash()
+            .into_iter()
+            .flat_map(|parent| self.tree.ancestors(parent))
+            .take_while(|ancestor| Some(ancestor.hash()) != fork_point)
+            .collect();
+        branch.reverse();
+        for block in branch.into_iter().chain([block]) {
+            Self::execute_transactions(&mut state, &self.params.reward_schedule, block)?;
+        }
+
+        self.state
+            .state_root_with(&state.into_changes())
+            .map_err(|error| BlockError::Store {
+                block_number,
+                error,
+            })
+    }
+
+    /// Proof of the value of `account_id` against the state root of the tip.
+    pub fn account_proof(&self, account_id: &AccountId) -> Result<AccountProof, StoreError> {
+        self.state.account_proof(account_id)
+    }
+
+    /// Hash of the block the state in the state store is at.
+    fn state_tip(&self) -> Result<Option<Hash>, StoreError> {
+        let tip = self.state.store().get(TIP_KEY)?;
+        Ok(tip.map(|tip| Hash::decode(&tip)).transpose()?)
+    }
+
+    /// Undo record of the canonical block `hash`, with the hash of its
+    /// parent.
+    fn load_undo(&self, hash: &Hash) -> Result<Option<(Option<Hash>, BlockUndo)>, StoreError> {
+        let Some(record) = self.state.store().get(&undo_key(hash))? else {
+            return Ok(None);
+        };
+        let mut input = record.as_slice();
+        let parent = Option::<Hash>::decode_from(&mut input)?;
+        Ok(Some((parent, BlockUndo::decode(input)?)))
+    }
+
+    /// Undo record of the canonical block `block`, which it must have.
+    fn canonical_undo(&self, block: &Block) -> Result<(Option<Hash>, BlockUndo), BlockError> {
+        self.load_undo(&block.hash())
+            .map_err(|error| BlockError::Store {
+                block_number: block.header.block_number,
+                error,
+            })?
+            .ok_or_else(|| BlockError::MissingUndo(block.hash()))
+    }
+
+    /// Makes the known block `tip` the canonical tip. Blocks of the current
+    /// branch down to the fork point are disconnected, then the blocks of the
+    /// new branch are connected. If a block of the new branch is invalid it
+    /// is dropped from the tree together with its descendants and the chain
+    /// is restored to where it was. A store error while connecting restores
+    /// the chain too but keeps the branch, to switch to once the store works
+    /// again. The transactions that fell out of the
+    /// canonical chain go back to the pool.
+    ///
+    /// A store error while restoring the old branch is returned as is and
+    /// leaves the tip at the last block that could be connected back; the
+    /// state always matches the tip.
+    pub fn reorg_to(&mut self, tip: &Hash) -> Result<Reorg, BlockError> {
+        let fork_point = self
+            .get_last_block_hash()
+            .and_then(|current_tip| self.tree.fork_point(&current_tip, tip))
+            .ok_or_else(|| BlockError::UnknownBlock(tip.clone()))?;
+
+        let mut new_branch: Vec<Block> = self
+            .tree
+            .ancestors(tip)
+            .take_while(|block| block.hash() != fork_point)
+            .cloned()
+            .collect();
+        new_branch.reverse();
+
+        let mut old_branch: Vec<Block> = vec![];
+        while self.get_last_block_hash().as_ref() != Some(&fork_point) {
+            old_branch.extend(self.disconnect_block()?);
+        }
+
+        for block in &new_branch {
+            if let Err(error) = self.connect_block(block.clone()) {
+                while self.get_last_block_hash().as_ref() != Some(&fork_point) {
+                    self.disconnect_block()?;
+                }
+                for block in old_branch.into_iter().rev() {
+                    self.connect_block(block)?;
+                }
+                if !matches!(error, BlockError::Store { .. }) {
+                    self.tree.remove_subtree(&block.hash());
+                }
+                return Err(error);
+            }
+        }
+
+        let connected_transactions: HashSet<Hash> = new_branch
+            .iter()
+            .flat_map(|block| block.transactions())
+            .map(|tx| tx.hash())
+            .collect();
+
+        let reorg = Reorg {
+            disconnected_blocks: old_branch.iter().map(|block| block.hash()).collect(),
+            connected_blocks: new_branch.iter().map(|block| block.hash()).collect(),
+            disconnected_transactions: old_branch
+                .iter()
+                .rev()
+                .flat_map(|block| block.transactions())
+                .filter(|tx| !tx.is_coinbase() && !connected_transactions.contains(&tx.hash()))
+                .cloned()
+                .collect(),
+        };
+
+        let mut pool = std::mem::take(&mut self.transaction_pool);
+        pool.readmit(
+            reorg.disconnected_transactions.clone(),
+            self,
+            self.clock.now(),
+        );
+        self.transaction_pool = pool;
+
+        Ok(reorg)
+    }
+
+    fn remove_stale_transactions(&mut self) {
+        let mut pool = std::mem::take(&mut self.transaction_pool);
+        pool.remove_stale(self);
+        pool.remove_expired(self.clock.now(), self.params.mempool_expiry);
+        self.transaction_pool = pool;
+    }
+
+    /// Drops pool transactions that have waited for longer than
+    /// `ConsensusParams::mempool_expiry`.
+    pub fn remove_expired_transactions(&mut self) {
+        self.transaction_pool
+            .remove_expired(self.clock.now(), self.params.mempool_expiry);
+    }
+
+    fn tip_cumulative_work(&self) -> BigInt {
+        self.get_last_block_hash()
+            .and_then(|hash| self.tree.get(&hash))
+            .map_or_else(|| BigInt::from(0), |entry| entry.cumulative_work.clone())
+    }
+
+    pub fn block_tree(&self) -> &BlockTree {
+        &self.tree
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.tree.get(hash).map(|entry| &entry.block)
+    }
+
+    /// Validates `tx` against the current state and the sender's pending
+    /// transactions and adds it to the pool.
+    pub fn submit_transaction(&mut self, tx: Transaction) -> Result<Hash, MempoolError> {
+        let now = self.clock.now();
+        let mut pool = std::mem::take(&mut self.transaction_pool);
+        pool.remove_expired(now, self.params.mempool_expiry);
+        let result = pool.insert(tx, self, now);
+        self.transaction_pool = pool;
+        result
+    }
+
+    pub fn transaction_pool(&self) -> &Mempool {
+        &self.transaction_pool
+    }
+
+    /// Block on top of the current tip filled with pool transactions that
+    /// are valid against the current state, ready to be mined. The subsidy
+    /// and all fees are paid to `coinbase_to`, which must be an existing
+    /// account.
+    pub fn build_block_template(&self, coinbase_to: AccountId) -> Block {
+        let coinbase = |amount| {
+            Transaction::new(
+                TransactionData::Coinbase {
+                    to: coinbase_to.clone(),
+                    amount,
+                },
+                None,
+            )
+        };
+
+        let mut block = Block::new(self.get_last_block_hash(), self.get_last_block_number());
+        block.header.bits = target_to_compact(&self.get_latest_target());
+        block.add_transaction(coinbase(0));
+
+        // The coinbase amount is fixed-width, so paying out the fees doesn't
+        // change the size of the block.
+        let space = self.params.max_block_size.saturating_sub(block.size());
+        let transactions = self.transaction_pool.select(self, space);
+        // Saturating, a reward that overflows makes the block invalid anyway.
+        let fees = transactions
+            .iter()
+            .fold(0, |fees: Balance, tx| fees.saturating_add(tx.fee()));
+        let subsidy = self.block_subsidy(self.blocks.len() as u128);
+
+        block.body.transactions[0] = coinbase(subsidy.saturating_add(fees));
+        for tx in transactions {
+            block.add_transaction(tx);
+        }
+        // A template that doesn't execute, e.g. because `coinbase_to` doesn't
+        // exist, keeps an empty root and is rejected for that reason.
+        if let Ok(state_root) = self.compute_state_root(&block) {
+            block.set_state_root(state_root);
+        }
+        block
+    }
+
+    pub fn block_subsidy(&self, block_number: u128) -> Balance {
+        self.params.reward_schedule.subsidy(block_number)
+    }
+
+    pub fn validate(&self) -> Result<(), BlockError> {
         let mut block_num = self.blocks.len();
         let mut prev_block_hash: Option<Hash> = None;
 
         for block in self.blocks.iter() {
-            let is_genesis = block.block_number == 0;
+            let block_number = block.header.block_number;
+            let is_genesis = block_number == 0;
 
-            if !block.verify(self.get_target(block.block_number)) {
-                return Err(format!("Block {} has invalid hash", block.block_number));
+            let target = self.get_target(block_number);
+            if block.header.bits() != target_to_compact(&target) {
+                return Err(BlockError::InvalidTarget { block_number });
+            }
+            if !block.verify(target) {
+                return Err(BlockError::InvalidHash { block_number });
             }
 
-            if !is_genesis && block.prev_hash.is_none() {
-                return Err(format!("Block {} doesn't have prev_hash", block_num));
+            if !is_genesis && block.header.prev_hash.is_none() {
+                return Err(BlockError::MissingPrevHash { block_number });
             }
 
-            if is_genesis && block.prev_hash.is_some() {
-                return Err("Genesis block shouldn't have prev_hash".to_string());
+            if is_genesis && block.header.prev_hash.is_some() {
+                return Err(BlockError::UnexpectedPrevHash { block_number });
             }
 
             if block_num != self.blocks.len() {
                 if let Some(prev_block_hash) = &prev_block_hash {
                     if prev_block_hash != &block.hash.clone().unwrap() {
-                        return Err(format!(
-                            "Block {} prev_hash doesn't match Block {} hash",
-                            block_num + 1,
-                            block_num
-                        ));
+                        return Err(BlockError::PrevHashMismatch {
+                            block_number: block_number + 1,
+                        });
                     }
                 }
             }
 
-            prev_block_hash = block.prev_hash.clone();
+            prev_block_hash = block.header.prev_hash.clone();
             block_num -= 1;
         }
 
         Ok(())
     }
 
-    pub fn get_latest_target(&self) -> BigInt {
-        self.get_target(self.blocks.len() as u128)
-    }
-
-    pub fn get_target(&self, block_number: u128) -> BigInt {
-        let initial_target: BigInt = BigInt::from(5) * BigInt::from(10).pow(74);
-
-        if self.blocks.len() == 0 {
-            return initial_target;
-        }
-
-        let mut target: BigInt = initial_target;
-
-        let mut blocks: Vec<&Block> = vec![];
+    /// Like `validate`, but also rebuilds the world state from genesis by
+    /// re-executing every canonical block. After each block the rebuilt
+    /// accounts are compared with the live ones as they were at that height,
+    /// which are recovered from the live state and the undo records of the
+    /// blocks above it. Reports the first block after which the two differ.
+    pub fn validate_full(&self) -> Result<(), BlockError> {
+        self.validate()?;
+
+        // Walk down from the tip, keeping the live value of the accounts each
+        // block touched as they were right after it. Only touched accounts
+        // are read from the store; accounts no block touched are caught by
+        // the state root of the tip at the end.
+        let mut live: HashMap<AccountId, Option<Account>> = HashMap::new();
+        let mut expected: Vec<Vec<AccountChange>> = Vec::with_capacity(self.blocks.len());
         for block in self.blocks.iter() {
-            blocks.push(block);
+            let (_, undo) = self.canonical_undo(block)?;
+            let mut after = Vec::with_capacity(undo.accounts().len());
+            for (account_id, _) in undo.accounts() {
+                let account = match live.get(account_id) {
+                    Some(account) => account.clone(),
+                    None => self
+                        .state
+                        .account(account_id)
+                        .map_err(|error| BlockError::Store {
+                            block_number: block.header.block_number,
+                            error,
+                        })?,
+                };
+                after.push((account_id.clone(), account));
+            }
+            expected.push(after);
+            for (account_id, account) in undo.accounts() {
+                live.insert(account_id.clone(), account.clone());
+            }
         }
 
-        let mut prev_timestamp: u128 = blocks[0].timestamp;
-        let mut last_block_generated = 0;
+        let mut replayed = JournaledState::new();
+        for (block, expected) in self.blocks.iter_from_genesis().zip(expected.iter().rev()) {
+            let block_number = block.header.block_number;
+            let undo = Self::execute_block(&mut replayed, &self.params.reward_schedule, block)?;
+
+            let touched: HashSet<&AccountId> = undo
+                .accounts()
+                .iter()
+                .map(|(account_id, _)| account_id)
+                .collect();
+            let diverged = undo
+                .accounts()
+                .iter()
+                .map(|(account_id, _)| account_id)
+                .find(|account_id| {
+                    !expected
+                        .iter()
+                        .any(|(expected_id, _)| expected_id == *account_id)
+                })
+                .or_else(|| {
+                    expected
+                        .iter()
+                        .find(|(account_id, account)| {
+                            !touched.contains(account_id)
+                                || replayed.get_account_by_id(account_id.clone()) != *account
+                        })
+                        .map(|(account_id, _)| account_id)
+                });
+
+            if let Some(account_id) = diverged {
+                return Err(BlockError::StateMismatch {
+                    block_number,
+                    account_id: account_id.clone(),
+                });
+            }
 
-        for block in blocks.into_iter().rev() {
-            if block.block_number == block_number {
-                break;
+            let state_root = replayed
+                .flush(vec![])
+                .and_then(|()| replayed.state_root())
+                .map_err(|error| BlockError::Store {
+                    block_number,
+                    error,
+                })?;
+            if &state_root != block.header.state_root() {
+                return Err(BlockError::InvalidStateRoot { block_number });
             }
+        }
 
-            if block.block_number > 0 && block.block_number - last_block_generated >= X {
-                last_block_generated = block.block_number;
+        // Accounts no block touched must not exist either.
+        if let Some(tip) = self.blocks.head() {
+            let block_number = tip.header.block_number;
+            let state_root = self.state.state_root().map_err(|error| BlockError::Store {
+                block_number,
+                error,
+            })?;
+            if &state_root != tip.header.state_root() {
+                return Err(BlockError::InvalidStateRoot { block_number });
+            }
+        }
 
-                let average_time =
-                    BigInt::from_i64(block.timestamp as i64 - prev_timestamp as i64).unwrap() / X;
+        Ok(())
+    }
 
-                let mut new_target = target.clone() * average_time / BigInt::from(EXPECTED_TIME);
+    /// Median timestamp of the last `median_time_span` blocks up to and
+    /// including `tip`. A block on top of `tip` may not be older than this.
+    pub fn median_time_past(&self, tip: &Hash) -> Timestamp {
+        let mut timestamps: Vec<Timestamp> = self
+            .tree
+            .ancestors(tip)
+            .take(self.params.median_time_span)
+            .map(|block| block.header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
 
-                new_target = min(
-                    new_target,
-                    target.clone() * BigInt::from_i32(MAX_TARGET_CHANGE).unwrap(),
-                );
-                new_target = max(
-                    new_target,
-                    target.clone() / BigInt::from_i32(MAX_TARGET_CHANGE).unwrap(),
-                );
+    pub fn get_latest_target(&self) -> BigInt {
+        self.get_next_target(self.get_last_block_hash().as_ref())
+    }
 
-                target = new_target;
-                prev_timestamp = block.timestamp;
+    /// Target of the canonical block `block_number`, or of the next block
+    /// past the tip.
+    pub fn get_target(&self, block_number: u128) -> BigInt {
+        match block_number.checked_sub(1) {
+            None => round_target(&self.params.initial_target),
+            Some(parent_number) => {
+                let parent = self.blocks.hash_at(parent_number as usize);
+                self.get_next_target(parent.or(self.blocks.head_hash()))
             }
         }
+    }
+
+    /// Target of a block built on `parent_hash`, on any known branch.
+    pub fn get_next_target(&self, parent_hash: Option<&Hash>) -> BigInt {
+        parent_hash
+            .and_then(|parent_hash| self.tree.get(parent_hash))
+            .map_or_else(
+                || round_target(&self.params.initial_target),
+                |parent| parent.next_target.clone(),
+            )
+    }
+
+    /// Target the children of `block` will have to meet, computed by the
+    /// difficulty algorithm from `block` and its ancestors and rounded to
+    /// what the compact encoding in their headers can hold.
+    fn compute_next_target(&self, block: &Block) -> BigInt {
+        let algorithm = &self.params.difficulty_algorithm;
+
+        let mut recent = vec![&block.header];
+        if let Some(parent_hash) = block.header.prev_hash() {
+            recent.extend(
+                self.tree
+                    .ancestors(parent_hash)
+                    .take(algorithm.lookback(&self.params).saturating_sub(1))
+                    .map(|block| &block.header),
+            );
+        }
+        let genesis = self
+            .tree
+            .genesis()
+            .and_then(|genesis| self.tree.get(genesis))
+            .map_or(&block.header, |genesis| &genesis.block.header);
 
-        target
+        round_target(&algorithm.next_target(&self.params, genesis, &recent))
     }
 
     pub fn get_last_block_hash(&self) -> Option<Hash> {
@@ -178,19 +926,30 @@ impl Blockchain {
     }
 
     pub fn get_last_block_number(&self) -> Option<u128> {
-        match self.blocks.head() {
-            None => None,
-            Some(x) => Some(x.block_number),
-        }
+        self.blocks.head().map(|block| block.header.block_number)
     }
 }
 
+fn undo_key(hash: &Hash) -> Vec<u8> {
+    [UNDO_PREFIX, hash.as_bytes()].concat()
+}
+
+/// Write moving the state store to `tip`.
+fn tip_write(tip: Option<&Hash>) -> (Vec<u8>, Option<Vec<u8>>) {
+    (TIP_KEY.to_vec(), tip.map(Encodable::encode))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{miner, TransactionData};
+    use crate::types::block_store::tests::TempDir;
+    use crate::types::{
+        miner, verify_account_proof, FileBlockStore, FileStateStore, Lwma, MemoryBlockStore,
+        MemoryStateStore, MockClock, StateBatch, StateEntry, TransactionData, TxError,
+    };
     use crate::utils;
     use crate::utils::{append_block, append_block_with_tx};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     #[test]
     fn test_new() {
@@ -258,10 +1017,14 @@ mod tests {
 
         miner::mine(&mut block, bc.get_latest_target());
 
-        assert_eq!(
-            bc.append_block(block).err().unwrap(),
-            "Error during tx execution: Invalid account.".to_string()
-        );
+        assert!(matches!(
+            bc.append_block(block),
+            Err(BlockError::Transaction {
+                block_number: 0,
+                tx_index: 0,
+                error: TxError::State(StateError::AccountNotFound(id)),
+            }) if id == account
+        ));
     }
 
     #[test]
@@ -290,6 +1053,7 @@ mod tests {
 
         block.add_transaction(tx_create_account);
         block.add_transaction(tx_mint_initial_supply);
+        block.set_state_root(bc.compute_state_root(&block).unwrap());
 
         miner::mine(&mut block, bc.get_latest_target());
 
@@ -299,6 +1063,13 @@ mod tests {
         let (account_bob, keypair_bob) = utils::generate_account_id();
 
         let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
+        let tx_coinbase = Transaction::new(
+            TransactionData::Coinbase {
+                to: account_satoshi.clone(),
+                amount: bc.block_subsidy(1),
+            },
+            None,
+        );
         let tx_create_alice = Transaction::new(
             TransactionData::CreateAccount {
                 account_id: account_alice.clone(),
@@ -314,6 +1085,7 @@ mod tests {
             None,
         );
 
+        block.add_transaction(tx_coinbase);
         block.add_transaction(tx_create_alice);
         block.add_transaction(tx_create_bob.clone());
         block.add_transaction(tx_create_bob);
@@ -322,7 +1094,10 @@ mod tests {
 
         assert!(bc.append_block(block).is_err());
 
-        assert!(bc.get_account_by_id(account_satoshi).is_some());
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi).unwrap().balance,
+            100_000_000
+        );
         assert!(bc.get_account_by_id(account_alice).is_none());
         assert!(bc.get_account_by_id(account_bob).is_none());
     }
@@ -355,13 +1130,14 @@ mod tests {
         append_block(bc);
 
         assert!(bc.validate().is_ok());
+        assert!(bc.validate_full().is_ok());
 
         let mut iter = bc.blocks.iter_mut();
         iter.next();
         iter.next();
         let block = iter.next().unwrap();
 
-        block.transactions[1].data = TransactionData::MintInitialSupply {
+        block.body.transactions[1].data = TransactionData::MintInitialSupply {
             to: account_satoshi,
             amount: 100,
         };
@@ -369,6 +1145,78 @@ mod tests {
         assert!(bc.validate().is_err());
     }
 
+    #[test]
+    fn test_validate_full() {
+        let bc = &mut Blockchain::new();
+
+        let (account_satoshi, keypair_satoshi) = utils::generate_account_id();
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        append_block_with_tx(
+            bc,
+            1,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_satoshi.clone(),
+                        public_key: keypair_satoshi.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_satoshi.clone(),
+                        amount: 100_000_000,
+                    },
+                    None,
+                ),
+            ],
+        )
+        .unwrap();
+        append_block(bc);
+
+        append_block_with_tx(
+            bc,
+            1,
+            vec![Transaction::new(
+                TransactionData::CreateAccount {
+                    account_id: account_alice.clone(),
+                    public_key: keypair_alice.public,
+                },
+                None,
+            )],
+        )
+        .unwrap();
+        append_block(bc);
+
+        assert!(bc.validate_full().is_ok());
+
+        // Headers and links are untouched, only the replay can notice.
+        bc.get_account_by_id_mut(account_satoshi.clone())
+            .unwrap()
+            .balance += 1;
+        assert!(bc.validate().is_ok());
+        assert_eq!(
+            bc.validate_full(),
+            Err(BlockError::StateMismatch {
+                block_number: 0,
+                account_id: account_satoshi.clone(),
+            })
+        );
+        bc.get_account_by_id_mut(account_satoshi).unwrap().balance -= 1;
+
+        bc.get_account_by_id_mut(account_alice.clone())
+            .unwrap()
+            .balance += 1;
+        assert_eq!(
+            bc.validate_full(),
+            Err(BlockError::StateMismatch {
+                block_number: 2,
+                account_id: account_alice,
+            })
+        );
+    }
+
     #[test]
     fn test_transfer() {
         let bc = &mut Blockchain::new();
@@ -446,18 +1294,995 @@ mod tests {
     }
 
     #[test]
-    fn test_signature() {
+    fn test_replay_protection() {
         let bc = &mut Blockchain::new();
 
-        let (account_1, account_1_keypair) = utils::generate_account_id();
-        let (account_2, account_2_keypair) = utils::generate_account_id();
+        let (account_alice, alice_keypair) = utils::generate_account_id();
+        let (account_bob, bob_keypair) = utils::generate_account_id();
 
-        let mut transfer_tx = Transaction::new(
+        assert!(utils::create_accounts_and_transfer(
+            bc,
+            account_alice.clone(),
+            account_bob.clone(),
+            account_alice.clone(),
+            account_bob.clone(),
+            100_000_000,
+            100_000,
+            &alice_keypair,
+            &bob_keypair
+        )
+        .is_ok());
+
+        assert_eq!(
+            bc.get_account_by_id(account_alice.clone()).unwrap().nonce,
+            1
+        );
+
+        let mut replayed_tx = Transaction::new(
             TransactionData::Transfer {
-                to: account_2.clone(),
+                to: account_bob.clone(),
                 amount: 100_000,
+                fee: 0,
             },
-            Some(account_1.clone()),
+            Some(account_alice.clone()),
+        );
+        replayed_tx.sign(&alice_keypair);
+
+        assert!(matches!(
+            append_block_with_tx(bc, 1, vec![replayed_tx]),
+            Err(BlockError::Transaction {
+                block_number: 1,
+                tx_index: 2,
+                error: TxError::InvalidNonce {
+                    expected: 1,
+                    got: 0
+                },
+            })
+        ));
+
+        let mut next_tx = Transaction::new(
+            TransactionData::Transfer {
+                to: account_bob.clone(),
+                amount: 100_000,
+                fee: 0,
+            },
+            Some(account_alice.clone()),
+        )
+        .with_nonce(1);
+        next_tx.sign(&alice_keypair);
+
+        assert!(append_block_with_tx(bc, 1, vec![next_tx]).is_ok());
+
+        let alice = bc.get_account_by_id(account_alice).unwrap();
+        assert_eq!(alice.nonce, 2);
+        assert_eq!(alice.balance, 99_800_000);
+        assert_eq!(bc.get_account_by_id(account_bob).unwrap().balance, 200_000);
+    }
+
+    fn mine_block(bc: &mut Blockchain, transactions: Vec<Transaction>) -> Result<(), BlockError> {
+        let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
+        for tx in transactions {
+            block.add_transaction(tx);
+        }
+        // Blocks that fail to execute are still mined, to check they are
+        // rejected.
+        if let Ok(state_root) = bc.compute_state_root(&block) {
+            block.set_state_root(state_root);
+        }
+        miner::mine_with_clock(&mut block, bc.get_latest_target(), bc.clock());
+        bc.append_block(block)
+    }
+
+    fn coinbase(to: &AccountId, amount: Balance) -> Transaction {
+        Transaction::new(
+            TransactionData::Coinbase {
+                to: to.clone(),
+                amount,
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn test_regtest_params() {
+        let clock = MockClock::new(1_000_000);
+        let bc = &mut Blockchain::new_with_params(ConsensusParams::regtest())
+            .with_clock(Arc::new(clock.clone()));
+
+        // Blocks far faster than the interval don't move the difficulty.
+        for _ in 0..6 {
+            clock.advance(1);
+            append_block(bc);
+        }
+        assert_eq!(bc.get_latest_target(), BigInt::from(1) << 255);
+        assert_eq!(bc.block_subsidy(150), 500);
+        assert!(bc.validate_full().is_ok());
+    }
+
+    #[test]
+    fn test_per_block_difficulty() {
+        let clock = MockClock::new(1_000_000);
+        let params = ConsensusParams {
+            difficulty_algorithm: Arc::new(Lwma { window: 10 }),
+            ..Default::default()
+        };
+        let initial_target = round_target(&params.initial_target);
+        let interval = params.block_interval;
+        let bc = &mut Blockchain::new_with_params(params).with_clock(Arc::new(clock.clone()));
+
+        append_block(bc);
+        assert_eq!(bc.get_latest_target(), initial_target);
+
+        // Blocks twice as fast as the interval make every next block harder.
+        let mut targets = vec![];
+        for _ in 0..4 {
+            clock.advance(interval / 2);
+            append_block(bc);
+            targets.push(bc.get_latest_target());
+        }
+        assert_eq!(targets[0], round_target(&(&initial_target / 2)));
+        assert!(targets.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(bc.validate_full().is_ok());
+    }
+
+    #[test]
+    fn test_max_block_size() {
+        let bc = &mut Blockchain::new_with_params(ConsensusParams {
+            max_block_size: 1_000,
+            ..Default::default()
+        });
+
+        let accounts: Vec<Transaction> = (0..10)
+            .map(|_| {
+                let (account_id, keypair) = utils::generate_account_id();
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id,
+                        public_key: keypair.public,
+                    },
+                    None,
+                )
+            })
+            .collect();
+
+        assert!(matches!(
+            mine_block(bc, accounts.clone()),
+            Err(BlockError::TooLarge {
+                block_number: 0,
+                max: 1_000,
+                ..
+            })
+        ));
+        assert!(mine_block(bc, accounts[..5].to_vec()).is_ok());
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let bc = &mut Blockchain::new_with_params(ConsensusParams {
+            reward_schedule: RewardSchedule {
+                initial_subsidy: 100,
+                halving_interval: 2,
+            },
+            ..Default::default()
+        });
+
+        let (account_satoshi, keypair_satoshi) = utils::generate_account_id();
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        assert!(mine_block(
+            bc,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_satoshi.clone(),
+                        public_key: keypair_satoshi.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_satoshi.clone(),
+                        amount: 1_000,
+                    },
+                    None,
+                ),
+            ]
+        )
+        .is_ok());
+
+        let create_alice = Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account_alice.clone(),
+                public_key: keypair_alice.public,
+            },
+            None,
+        );
+
+        assert!(mine_block(bc, vec![create_alice.clone()]).is_err());
+        assert!(mine_block(
+            bc,
+            vec![create_alice.clone(), coinbase(&account_satoshi, 100)]
+        )
+        .is_err());
+        assert!(mine_block(
+            bc,
+            vec![coinbase(&account_satoshi, 101), create_alice.clone()]
+        )
+        .is_err());
+        assert!(mine_block(
+            bc,
+            vec![
+                coinbase(&account_satoshi, 100),
+                coinbase(&account_satoshi, 100),
+                create_alice.clone()
+            ]
+        )
+        .is_err());
+        assert!(mine_block(bc, vec![coinbase(&account_satoshi, 100), create_alice]).is_ok());
+
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi.clone())
+                .unwrap()
+                .balance,
+            1_100
+        );
+
+        // Block 2 is past the first halving, fees are added on top.
+        let mut transfer_tx = Transaction::new(
+            TransactionData::Transfer {
+                to: account_alice.clone(),
+                amount: 10,
+                fee: 7,
+            },
+            Some(account_satoshi.clone()),
+        );
+        transfer_tx.sign(&keypair_satoshi);
+
+        assert!(mine_block(
+            bc,
+            vec![coinbase(&account_satoshi, 58), transfer_tx.clone()]
+        )
+        .is_err());
+        assert!(mine_block(bc, vec![coinbase(&account_satoshi, 57), transfer_tx]).is_ok());
+
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi).unwrap().balance,
+            1_100 - 17 + 57
+        );
+        assert_eq!(bc.get_account_by_id(account_alice).unwrap().balance, 10);
+    }
+
+    #[test]
+    fn test_reward_overflow() {
+        let bc = &mut Blockchain::new();
+
+        let (account_satoshi, keypair_satoshi) = utils::generate_account_id();
+        assert!(mine_block(
+            bc,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_satoshi.clone(),
+                        public_key: keypair_satoshi.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_satoshi.clone(),
+                        amount: Balance::MAX,
+                    },
+                    None,
+                ),
+            ]
+        )
+        .is_ok());
+
+        let mut transfer_tx = Transaction::new(
+            TransactionData::Transfer {
+                to: account_satoshi.clone(),
+                amount: 0,
+                fee: Balance::MAX,
+            },
+            Some(account_satoshi.clone()),
+        );
+        transfer_tx.sign(&keypair_satoshi);
+        assert_eq!(
+            mine_block(bc, vec![coinbase(&account_satoshi, 0), transfer_tx]),
+            Err(BlockError::RewardOverflow { block_number: 1 })
+        );
+
+        assert_eq!(
+            mine_block(bc, vec![coinbase(&account_satoshi, 1)]),
+            Err(BlockError::Transaction {
+                block_number: 1,
+                tx_index: 0,
+                error: TxError::BalanceOverflow {
+                    account_id: account_satoshi.clone(),
+                },
+            })
+        );
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi).unwrap().balance,
+            Balance::MAX
+        );
+    }
+
+    fn mine_on(bc: &Blockchain, parent: &Block, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(Some(parent.hash()), Some(parent.header.block_number));
+        for tx in transactions {
+            block.add_transaction(tx);
+        }
+        if let Ok(state_root) = bc.compute_state_root(&block) {
+            block.set_state_root(state_root);
+        }
+        miner::mine(&mut block, bc.get_next_target(Some(&parent.hash())));
+        block
+    }
+
+    #[test]
+    fn test_fork_choice() {
+        let bc = &mut Blockchain::new();
+
+        let (account_satoshi, keypair_satoshi) = utils::generate_account_id();
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        assert!(mine_block(
+            bc,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_satoshi.clone(),
+                        public_key: keypair_satoshi.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_satoshi.clone(),
+                        amount: 1_000,
+                    },
+                    None,
+                ),
+            ]
+        )
+        .is_ok());
+        let genesis = bc.blocks.head().unwrap().clone();
+        let subsidy = bc.block_subsidy(1);
+
+        let a1 = mine_on(bc, &genesis, vec![coinbase(&account_satoshi, subsidy)]);
+        assert!(bc.append_block(a1.clone()).is_ok());
+
+        let b1 = mine_on(
+            bc,
+            &genesis,
+            vec![
+                coinbase(&account_satoshi, subsidy),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_alice.clone(),
+                        public_key: keypair_alice.public,
+                    },
+                    None,
+                ),
+            ],
+        );
+
+        // Same work as the current branch, so the first seen branch stays.
+        assert!(bc.append_block(b1.clone()).is_ok());
+        assert_eq!(bc.get_last_block_hash(), Some(a1.hash()));
+        assert!(bc.get_account_by_id(account_alice.clone()).is_none());
+        assert!(bc.append_block(b1.clone()).is_err());
+
+        let b2 = mine_on(bc, &b1, vec![coinbase(&account_alice, subsidy)]);
+        assert!(bc.append_block(b2.clone()).is_ok());
+
+        assert_eq!(bc.get_last_block_hash(), Some(b2.hash()));
+        assert_eq!(bc.len(), 3);
+        assert_eq!(bc.block_tree().len(), 4);
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi.clone())
+                .unwrap()
+                .balance,
+            1_000 + subsidy
+        );
+        assert_eq!(
+            bc.get_account_by_id(account_alice.clone()).unwrap().balance,
+            subsidy
+        );
+        assert!(bc.validate().is_ok());
+
+        // A heavier branch with an invalid block is dropped.
+        let x2 = mine_on(bc, &a1, vec![coinbase(&account_satoshi, subsidy + 1)]);
+        assert!(bc.append_block(x2.clone()).is_ok());
+        let x3 = mine_on(bc, &x2, vec![coinbase(&account_satoshi, subsidy)]);
+        assert!(bc.append_block(x3.clone()).is_err());
+
+        assert_eq!(bc.get_last_block_hash(), Some(b2.hash()));
+        assert!(bc.get_block_by_hash(&x2.hash()).is_none());
+        assert!(bc.get_block_by_hash(&x3.hash()).is_none());
+        assert_eq!(
+            bc.get_account_by_id(account_alice).unwrap().balance,
+            subsidy
+        );
+    }
+
+    #[test]
+    fn test_block_store() {
+        let dir = TempDir::new("blockchain");
+        let open = || {
+            let store = FileBlockStore::open(&dir.0).unwrap();
+            Blockchain::new().with_store(Box::new(store)).unwrap()
+        };
+
+        let bc = &mut open();
+        for _ in 0..3 {
+            append_block(bc);
+        }
+        let genesis = bc.blocks.get(0).unwrap().clone();
+        let (account, keypair) = utils::generate_account_id();
+        let side = mine_on(
+            bc,
+            &genesis,
+            vec![
+                coinbase(&account, 0),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account,
+                        public_key: keypair.public,
+                    },
+                    None,
+                ),
+            ],
+        );
+        assert!(bc.append_block(side.clone()).is_ok());
+        let tip = bc.get_last_block_hash();
+        let accounts = bc.state.accounts().unwrap();
+
+        let reopened = &mut open();
+        assert_eq!(reopened.get_last_block_hash(), tip);
+        assert_eq!(reopened.len(), 3);
+        assert!(reopened.tree.contains(&side.hash()));
+        for (account_id, account) in accounts {
+            assert_eq!(
+                reopened.get_account_by_id(account_id).map(|a| a.balance),
+                Some(account.balance)
+            );
+        }
+        assert!(reopened.validate_full().is_ok());
+
+        append_block(reopened);
+        assert_eq!(open().len(), 4);
+    }
+
+    /// Block store whose writes fail while `fail` is set.
+    #[derive(Debug, Default)]
+    struct FlakyBlockStore {
+        blocks: MemoryBlockStore,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl BlockStore for FlakyBlockStore {
+        fn append(&mut self, block: &Block) -> Result<(), StoreError> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(StoreError::Io("disk full".to_string()));
+            }
+            self.blocks.append(block)
+        }
+
+        fn len(&self) -> usize {
+            self.blocks.len()
+        }
+
+        fn get(&self, index: usize) -> Result<Option<Block>, StoreError> {
+            self.blocks.get(index)
+        }
+    }
+
+    /// State store whose write fails when `fail_in` counts down to it.
+    #[derive(Debug, Default)]
+    struct FlakyStateStore {
+        state: MemoryStateStore,
+        fail_in: Arc<AtomicUsize>,
+    }
+
+    impl StateStore for FlakyStateStore {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+            self.state.get(key)
+        }
+
+        fn write(&mut self, batch: StateBatch) -> Result<(), StoreError> {
+            let countdown = self
+                .fail_in
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            if countdown == Ok(1) {
+                return Err(StoreError::Io("disk full".to_string()));
+            }
+            self.state.write(batch)
+        }
+
+        fn scan(&self, prefix: &[u8]) -> Result<Vec<StateEntry>, StoreError> {
+            self.state.scan(prefix)
+        }
+    }
+
+    #[test]
+    fn test_reorg_store_failure() {
+        let store = FlakyStateStore::default();
+        let fail_in = store.fail_in.clone();
+        let bc = &mut Blockchain::new().with_state_store(Box::new(store));
+        append_block(bc);
+        append_block(bc);
+        let genesis = bc.blocks.get(0).unwrap().clone();
+        let tip = bc.blocks.head().unwrap().clone();
+        let accounts = bc.state.accounts().unwrap();
+
+        let new_account = || {
+            let (account, keypair) = utils::generate_account_id();
+            vec![
+                coinbase(&account, 0),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account,
+                        public_key: keypair.public,
+                    },
+                    None,
+                ),
+            ]
+        };
+        let side = mine_on(bc, &genesis, new_account());
+        assert!(bc.append_block(side.clone()).is_ok());
+
+        // Disconnecting the tip is written, connecting `side` isn't.
+        fail_in.store(2, Ordering::SeqCst);
+        let heavier = mine_on(bc, &side, new_account());
+        assert!(matches!(
+            bc.append_block(heavier.clone()),
+            Err(BlockError::Store {
+                block_number: 1,
+                error: StoreError::Io(_),
+            })
+        ));
+        assert_eq!(bc.get_last_block_hash(), Some(tip.hash()));
+        assert_eq!(bc.state.accounts().unwrap(), accounts);
+        assert!(bc.tree.contains(&heavier.hash()));
+        assert!(bc.validate_full().is_ok());
+
+        // The branch is still there to switch to once the store recovers.
+        let on_heavier = mine_on(bc, &heavier, new_account());
+        assert!(bc.append_block(on_heavier.clone()).is_ok());
+        assert_eq!(bc.get_last_block_hash(), Some(on_heavier.hash()));
+        assert!(bc.validate_full().is_ok());
+    }
+
+    #[test]
+    fn test_block_store_failure() {
+        let store = FlakyBlockStore::default();
+        let fail = store.fail.clone();
+        let bc = &mut Blockchain::new().with_store(Box::new(store)).unwrap();
+        append_block(bc);
+        append_block(bc);
+        let genesis = bc.blocks.get(0).unwrap().clone();
+        let tip = bc.blocks.head().unwrap().clone();
+        let accounts = bc.state.accounts().unwrap();
+
+        let new_account = || {
+            let (account, keypair) = utils::generate_account_id();
+            vec![
+                coinbase(&account, 0),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account,
+                        public_key: keypair.public,
+                    },
+                    None,
+                ),
+            ]
+        };
+        let side = mine_on(bc, &genesis, new_account());
+        assert!(bc.append_block(side.clone()).is_ok());
+
+        fail.store(true, Ordering::SeqCst);
+        let on_tip = mine_on(bc, &tip, new_account());
+        let heavier = mine_on(bc, &side, new_account());
+        for block in [on_tip, heavier] {
+            assert!(matches!(
+                bc.append_block(block.clone()),
+                Err(BlockError::Store {
+                    block_number: 2,
+                    error: StoreError::Io(_),
+                })
+            ));
+            assert!(!bc.tree.contains(&block.hash()));
+            assert_eq!(bc.get_last_block_hash(), Some(tip.hash()));
+            assert_eq!(bc.state.accounts().unwrap(), accounts);
+        }
+        assert!(bc.tree.contains(&side.hash()));
+        assert!(bc.validate_full().is_ok());
+
+        fail.store(false, Ordering::SeqCst);
+        append_block(bc);
+        assert_eq!(bc.store.as_ref().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_state_store() {
+        let dir = TempDir::new("blockchain_state");
+        let open = || {
+            let state = FileStateStore::open(dir.0.join("state")).unwrap();
+            let blocks = FileBlockStore::open(dir.0.join("blocks")).unwrap();
+            Blockchain::new()
+                .with_state_store(Box::new(state))
+                .with_store(Box::new(blocks))
+                .unwrap()
+        };
+
+        let mut bc = open();
+        for _ in 0..3 {
+            append_block(&mut bc);
+        }
+        let tip = bc.get_last_block_hash();
+        let accounts = bc.state.accounts().unwrap();
+        drop(bc);
+
+        // The blocks are checked again but not executed, which would fail on
+        // accounts that already exist.
+        let mut reopened = open();
+        assert_eq!(reopened.get_last_block_hash(), tip);
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.state.accounts().unwrap(), accounts);
+        assert!(reopened.validate_full().is_ok());
+
+        // A crash after the state was written but before the block was.
+        let mut block = Block::new(tip.clone(), reopened.get_last_block_number());
+        block.add_transaction(coinbase(&accounts[0].0, reopened.block_subsidy(3)));
+        block.set_state_root(reopened.compute_state_root(&block).unwrap());
+        miner::mine_with_clock(&mut block, reopened.get_latest_target(), reopened.clock());
+        assert!(reopened.accept_block(block).is_ok());
+        assert_ne!(reopened.state.accounts().unwrap(), accounts);
+        drop(reopened);
+
+        let mut reopened = open();
+        assert_eq!(reopened.get_last_block_hash(), tip);
+        assert_eq!(reopened.state.accounts().unwrap(), accounts);
+
+        append_block(&mut reopened);
+        assert!(reopened.validate_full().is_ok());
+        drop(reopened);
+        assert_eq!(open().len(), 4);
+
+        // A lost undo record is reported, not a reason to abort.
+        let tip = open().get_last_block_hash().unwrap();
+        let mut state = FileStateStore::open(dir.0.join("state")).unwrap();
+        state.write(vec![(undo_key(&tip), None)]).unwrap();
+        drop(state);
+
+        let mut reopened = open();
+        let parent = reopened
+            .get_block_by_hash(&tip)
+            .unwrap()
+            .header
+            .prev_hash
+            .clone();
+        assert!(matches!(
+            reopened.reorg_to(&parent.unwrap()),
+            Err(BlockError::MissingUndo(hash)) if hash == tip
+        ));
+        assert_eq!(reopened.get_last_block_hash(), Some(tip));
+    }
+
+    #[test]
+    fn test_reorg() {
+        let bc = &mut Blockchain::new();
+
+        let (account_satoshi, keypair_satoshi) = utils::generate_account_id();
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        assert!(mine_block(
+            bc,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_satoshi.clone(),
+                        public_key: keypair_satoshi.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_alice.clone(),
+                        public_key: keypair_alice.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_satoshi.clone(),
+                        amount: 1_000,
+                    },
+                    None,
+                ),
+            ]
+        )
+        .is_ok());
+        let genesis = bc.blocks.head().unwrap().clone();
+        let subsidy = bc.block_subsidy(1);
+
+        let transfer = |nonce| {
+            let mut tx = Transaction::new(
+                TransactionData::Transfer {
+                    to: account_alice.clone(),
+                    amount: 100,
+                    fee: 0,
+                },
+                Some(account_satoshi.clone()),
+            )
+            .with_nonce(nonce);
+            tx.sign(&keypair_satoshi);
+            tx
+        };
+
+        let a1 = mine_on(
+            bc,
+            &genesis,
+            vec![coinbase(&account_satoshi, subsidy), transfer(0)],
+        );
+        assert!(bc.append_block(a1.clone()).is_ok());
+        assert!(bc.submit_transaction(transfer(1)).is_ok());
+
+        let b1 = mine_on(bc, &genesis, vec![coinbase(&account_alice, subsidy)]);
+        assert!(bc.append_block(b1.clone()).is_ok());
+        let b2 = mine_on(bc, &b1, vec![coinbase(&account_alice, subsidy)]);
+        assert!(bc.append_block(b2.clone()).is_ok());
+
+        assert_eq!(bc.get_last_block_hash(), Some(b2.hash()));
+        let satoshi = bc.get_account_by_id(account_satoshi.clone()).unwrap();
+        assert_eq!((satoshi.balance, satoshi.nonce), (1_000, 0));
+        assert_eq!(
+            bc.get_account_by_id(account_alice.clone()).unwrap().balance,
+            2 * subsidy
+        );
+        // The disconnected transfer is back in the pool ahead of the pending one.
+        assert_eq!(bc.transaction_pool().len(), 2);
+        assert!(bc.transaction_pool().contains(&transfer(0).hash()));
+        assert!(bc.transaction_pool().contains(&transfer(1).hash()));
+
+        let reorg = bc.reorg_to(&a1.hash()).unwrap();
+        assert_eq!(reorg.disconnected_blocks, vec![b2.hash(), b1.hash()]);
+        assert_eq!(reorg.connected_blocks, vec![a1.hash()]);
+        assert!(reorg.disconnected_transactions.is_empty());
+        // Called directly, the pool is still updated.
+        assert_eq!(bc.transaction_pool().len(), 1);
+        assert!(bc.transaction_pool().contains(&transfer(1).hash()));
+
+        assert_eq!(bc.get_last_block_hash(), Some(a1.hash()));
+        assert_eq!(
+            bc.get_account_by_id(account_satoshi.clone())
+                .unwrap()
+                .balance,
+            1_000 - 100 + subsidy
+        );
+        assert_eq!(
+            bc.get_account_by_id(account_alice.clone()).unwrap().balance,
+            100
+        );
+        assert!(bc.validate().is_ok());
+
+        let reorg = bc.reorg_to(&b2.hash()).unwrap();
+        assert_eq!(reorg.disconnected_transactions, vec![transfer(0)]);
+        assert_eq!(bc.transaction_pool().len(), 2);
+        assert!(bc.reorg_to(&"00".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_header_commitments() {
+        let bc = &mut Blockchain::new();
+        append_block(bc);
+        let genesis = bc.blocks.head().unwrap().clone();
+
+        let (account_miner, keypair_miner) = utils::generate_account_id();
+        let mut block = Block::new(Some(genesis.hash()), Some(0));
+        block.add_transaction(coinbase(&account_miner, 0));
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account_miner,
+                public_key: keypair_miner.public,
+            },
+            None,
+        ));
+        block.set_state_root(bc.compute_state_root(&block).unwrap());
+        miner::mine(&mut block, bc.get_latest_target());
+
+        // Rewriting the timestamp invalidates the proof of work. The test
+        // target is easy enough for the old nonce to still meet it by chance,
+        // so keep going until it doesn't.
+        let mut rewritten = block.clone();
+        loop {
+            rewritten.header.timestamp += 1;
+            rewritten.update_hash();
+            if !rewritten.verify(bc.get_latest_target()) {
+                break;
+            }
+        }
+        assert_eq!(
+            bc.append_block(rewritten.clone()),
+            Err(BlockError::InvalidHash { block_number: 1 })
+        );
+
+        // Redoing the work doesn't help if the timestamp runs backwards.
+        rewritten.header.timestamp = genesis.header.timestamp - 1;
+        solve(&mut rewritten);
+        assert_eq!(
+            bc.append_block(rewritten.clone()),
+            Err(BlockError::TimestampTooOld {
+                block_number: 1,
+                timestamp: genesis.header.timestamp - 1,
+                min: genesis.header.timestamp,
+            })
+        );
+
+        let mut easier = block.clone();
+        easier.header.bits = target_to_compact(&(bc.get_latest_target() * 2));
+        solve(&mut easier);
+        assert_eq!(
+            bc.append_block(easier),
+            Err(BlockError::InvalidTarget { block_number: 1 })
+        );
+
+        assert!(bc.append_block(block).is_ok());
+    }
+
+    #[test]
+    fn test_state_root() {
+        let bc = &mut Blockchain::new();
+        append_block(bc);
+        append_block(bc);
+        let accounts = bc.state.accounts().unwrap();
+
+        let (account_miner, keypair_miner) = utils::generate_account_id();
+        let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
+        block.add_transaction(coinbase(&account_miner, bc.block_subsidy(2)));
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account_miner.clone(),
+                public_key: keypair_miner.public,
+            },
+            None,
+        ));
+        let state_root = bc.compute_state_root(&block).unwrap();
+
+        // The root of the state before the block.
+        let mut stale = block.clone();
+        stale.set_state_root(bc.state.state_root().unwrap());
+        miner::mine(&mut stale, bc.get_latest_target());
+        assert_eq!(
+            bc.append_block(stale),
+            Err(BlockError::InvalidStateRoot { block_number: 2 })
+        );
+        assert_eq!(bc.state.accounts().unwrap(), accounts);
+
+        block.set_state_root(state_root.clone());
+        miner::mine(&mut block, bc.get_latest_target());
+        assert!(bc.append_block(block).is_ok());
+        assert_eq!(bc.state.state_root(), Ok(state_root.clone()));
+        assert!(bc.validate_full().is_ok());
+
+        let proof = bc.account_proof(&account_miner).unwrap();
+        assert_eq!(proof.account.as_ref().unwrap().balance, bc.block_subsidy(2));
+        assert!(verify_account_proof(&state_root, &account_miner, &proof));
+        assert!(!verify_account_proof(&state_root, &accounts[0].0, &proof));
+
+        let (missing, _) = utils::generate_account_id();
+        let proof = bc.account_proof(&missing).unwrap();
+        assert!(proof.account.is_none());
+        assert!(verify_account_proof(&state_root, &missing, &proof));
+    }
+
+    #[test]
+    fn test_timestamp_rules() {
+        let clock = MockClock::new(0);
+        let bc = &mut Blockchain::new_with_params(ConsensusParams {
+            max_future_drift: 1_000,
+            ..Default::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+
+        let genesis = block_at(bc, 10_000);
+        assert_eq!(
+            bc.append_block(genesis.clone()),
+            Err(BlockError::TimestampTooNew {
+                block_number: 0,
+                timestamp: 10_000,
+                max: 1_000,
+            })
+        );
+        clock.set(9_000);
+        assert!(bc.append_block(genesis).is_ok());
+
+        clock.set(10_000);
+        for timestamp in [10_100, 10_200] {
+            let block = block_at(bc, timestamp);
+            assert!(bc.append_block(block).is_ok());
+        }
+
+        // Behind the parent but not behind the median of the last blocks.
+        let block = block_at(bc, 10_150);
+        assert!(bc.append_block(block).is_ok());
+
+        let block = block_at(bc, 10_120);
+        assert_eq!(
+            bc.append_block(block),
+            Err(BlockError::TimestampTooOld {
+                block_number: 4,
+                timestamp: 10_120,
+                min: 10_150,
+            })
+        );
+    }
+
+    /// Block on top of the tip with the given timestamp, paying the reward to
+    /// a new account.
+    fn block_at(bc: &Blockchain, timestamp: Timestamp) -> Block {
+        let (account, keypair) = utils::generate_account_id();
+        let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
+        block.add_transaction(coinbase(&account, 0));
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account,
+                public_key: keypair.public,
+            },
+            None,
+        ));
+        block.set_state_root(bc.compute_state_root(&block).unwrap());
+        block.header.timestamp = timestamp;
+        block.header.bits = target_to_compact(&bc.get_latest_target());
+        solve(&mut block);
+        block
+    }
+
+    /// Finds a nonce for the target and timestamp already in the header.
+    fn solve(block: &mut Block) {
+        let target = block.header.target();
+        block.set_nonce(0);
+        while !block.header.meets_target(&target) {
+            block.set_nonce(block.header.nonce + 1);
+        }
+    }
+
+    #[test]
+    fn test_unknown_parent() {
+        let bc = &mut Blockchain::new();
+        append_block(bc);
+
+        let orphan = Block::new(Some("00".repeat(32)), Some(0));
+        assert_eq!(
+            bc.append_block(orphan),
+            Err(BlockError::UnknownParent { block_number: 1 })
+        );
+
+        let mut second_genesis = Block::new(None, None);
+        second_genesis.add_transaction(coinbase(&"".to_string(), 0));
+        miner::mine(&mut second_genesis, bc.get_next_target(None));
+        assert_eq!(
+            bc.append_block(second_genesis),
+            Err(BlockError::GenesisAlreadyExists)
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let bc = &mut Blockchain::new();
+
+        let (account_1, account_1_keypair) = utils::generate_account_id();
+        let (account_2, account_2_keypair) = utils::generate_account_id();
+
+        let mut transfer_tx = Transaction::new(
+            TransactionData::Transfer {
+                to: account_2.clone(),
+                amount: 100_000,
+                fee: 0,
+            },
+            Some(account_1.clone()),
         );
 
         assert!(append_block_with_tx(
@@ -525,21 +2350,32 @@ mod tests {
 
     #[test]
     fn test_target() {
-        let bc = &mut Blockchain::new();
+        let clock = MockClock::new(1_000_000);
+        let bc = &mut Blockchain::new().with_clock(Arc::new(clock.clone()));
+        let initial_target = bc.get_next_target(None);
 
-        for _ in 0..12 {
+        // Two hours of blocks on schedule keep the target where it is.
+        append_block(bc);
+        for _ in 0..120 {
+            clock.advance(bc.params().block_interval);
             append_block(bc);
         }
+        assert_eq!(bc.get_latest_target(), initial_target);
 
-        let mut prev_time = bc.blocks.head().unwrap().timestamp;
-
-        for block in bc.blocks.iter() {
-            dbg!(&prev_time - &block.timestamp);
-            prev_time = block.timestamp;
+        // Blocks coming twice as fast halve the target on every retarget.
+        for _ in 0..4 {
+            clock.advance(bc.params().block_interval / 2);
+            append_block(bc);
         }
+        let halved = round_target(&(initial_target / 2));
+        assert_eq!(bc.get_latest_target(), round_target(&(halved / 2)));
 
-        for i in 0..bc.blocks.len() {
-            dbg!((i, bc.get_target(i as u128)));
+        for block in bc.blocks.iter() {
+            assert_eq!(
+                bc.get_target(block.header.block_number),
+                block.header.target()
+            );
         }
+        assert_eq!(bc.get_target(bc.len() as u128), bc.get_latest_target());
     }
 }
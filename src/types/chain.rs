@@ -1,81 +1,112 @@
-#[derive(Default, Debug)]
-pub struct Node<T> {
-    data: T,
-    prev: Option<Box<Node<T>>>,
+use crate::traits::Hashable;
+use crate::types::Hash;
+use std::collections::HashMap;
+use std::iter::Rev;
+use std::ops::{Bound, RangeBounds};
+use std::slice;
+
+/// Items stored by height, genesis first, with an index from hash to height.
+///
+/// The index is keyed on the hash an item had when it was appended, so items
+/// changed through `iter_mut` are still found under their old hash.
+#[derive(Debug)]
+pub struct Chain<T> {
+    items: Vec<T>,
+    /// Hash of each item when it was appended, by height.
+    hashes: Vec<Hash>,
+    by_hash: HashMap<Hash, usize>,
 }
 
-#[derive(Default, Debug)]
-pub struct Chain<T> {
-    head: Option<Box<Node<T>>>,
-    len: usize,
+impl<T> Default for Chain<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            hashes: Vec::new(),
+            by_hash: HashMap::new(),
+        }
+    }
 }
 
-impl<'a, T> Iterator for ChainIter<'a, T> {
-    type Item = &'a T;
+impl<T: Hashable> Chain<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.next.take().map(|node| {
-            self.next = node.prev.as_deref();
-            &node.data
-        })
+    pub fn append(&mut self, item: T) {
+        let hash = item.hash();
+        self.by_hash.insert(hash.clone(), self.items.len());
+        self.hashes.push(hash);
+        self.items.push(item);
     }
-}
 
-impl<'a, T> Iterator for ChainIterMut<'a, T> {
-    type Item = &'a mut T;
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.items.pop()?;
+        if let Some(hash) = self.hashes.pop() {
+            self.by_hash.remove(&hash);
+        }
+        Some(item)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.next.take().map(|node| {
-            self.next = node.prev.as_deref_mut();
-            &mut node.data
-        })
+    pub fn get_by_hash(&self, hash: &Hash) -> Option<&T> {
+        self.height_of(hash).map(|height| &self.items[height])
     }
-}
 
-pub struct ChainIter<'a, T> {
-    next: Option<&'a Node<T>>,
+    pub fn height_of(&self, hash: &Hash) -> Option<usize> {
+        self.by_hash.get(hash).copied()
+    }
 }
 
-pub struct ChainIterMut<'a, T> {
-    next: Option<&'a mut Node<T>>,
-}
+impl<T> Chain<T> {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
 
-impl<T: Default> Chain<T> {
-    pub fn new() -> Self {
-        Default::default()
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
     }
 
-    pub fn append(&mut self, item: T) {
-        let head = self.head.take();
-        let node = Box::new(Node {
-            data: item,
-            prev: head,
-        });
-        self.head = Some(node);
-        self.len += 1;
+    pub fn head(&self) -> Option<&T> {
+        self.items.last()
     }
 
-    pub fn len(&self) -> usize {
-        self.len
+    pub fn get(&self, height: usize) -> Option<&T> {
+        self.items.get(height)
     }
 
-    pub fn head(&self) -> Option<&T> {
-        match &self.head {
-            None => None,
-            Some(head) => Some(&head.data),
-        }
+    /// Hash the item at `height` had when it was appended.
+    pub fn hash_at(&self, height: usize) -> Option<&Hash> {
+        self.hashes.get(height)
     }
 
-    pub fn iter(&self) -> ChainIter<T> {
-        ChainIter {
-            next: self.head.as_deref(),
-        }
+    pub fn head_hash(&self) -> Option<&Hash> {
+        self.hashes.last()
     }
 
-    pub fn iter_mut(&mut self) -> ChainIterMut<T> {
-        ChainIterMut {
-            next: self.head.as_deref_mut(),
-        }
+    /// Items from the head back to genesis.
+    pub fn iter(&self) -> Rev<slice::Iter<'_, T>> {
+        self.items.iter().rev()
+    }
+
+    /// Items from the head back to genesis.
+    pub fn iter_mut(&mut self) -> Rev<slice::IterMut<'_, T>> {
+        self.items.iter_mut().rev()
+    }
+
+    /// Items from genesis up to the head.
+    pub fn iter_from_genesis(&self) -> slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    /// Items at `heights`, in ascending order. Heights past the head are
+    /// left out.
+    pub fn range(&self, heights: impl RangeBounds<usize>) -> slice::Iter<'_, T> {
+        let start = heights.start_bound().cloned();
+        let end = match heights.end_bound().cloned() {
+            Bound::Included(end) if end >= self.len() => Bound::Unbounded,
+            Bound::Excluded(end) if end > self.len() => Bound::Unbounded,
+            end => end,
+        };
+        self.items.get((start, end)).unwrap_or(&[]).iter()
     }
 }
 
@@ -83,6 +114,12 @@ impl<T: Default> Chain<T> {
 mod tests {
     use super::*;
 
+    impl Hashable for u32 {
+        fn hash(&self) -> Hash {
+            self.to_string()
+        }
+    }
+
     #[test]
     fn test_append() {
         let mut chain = Chain::<u32>::new();
@@ -92,6 +129,21 @@ mod tests {
         assert_eq!(chain.head(), Some(&1));
     }
 
+    #[test]
+    fn test_pop() {
+        let mut chain = Chain::<u32>::new();
+        chain.append(1);
+        chain.append(2);
+
+        assert_eq!(chain.pop(), Some(2));
+        assert_eq!(chain.head(), Some(&1));
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain.get_by_hash(&"2".to_string()), None);
+        assert_eq!(chain.pop(), Some(1));
+        assert_eq!(chain.pop(), None);
+        assert!(chain.is_empty());
+    }
+
     #[test]
     fn test_append_loop() {
         let mut chain = Chain::<u32>::new();
@@ -104,4 +156,40 @@ mod tests {
             dbg!(i);
         }
     }
+
+    #[test]
+    fn test_random_access() {
+        let mut chain = Chain::<u32>::new();
+        for i in 10..20 {
+            chain.append(i);
+        }
+
+        assert_eq!(chain.get(0), Some(&10));
+        assert_eq!(chain.get(9), Some(&19));
+        assert_eq!(chain.get(10), None);
+        assert_eq!(chain.get_by_hash(&"15".to_string()), Some(&15));
+        assert_eq!(chain.height_of(&"15".to_string()), Some(5));
+        assert_eq!(chain.get_by_hash(&"20".to_string()), None);
+        assert_eq!(chain.hash_at(3), Some(&"13".to_string()));
+        assert_eq!(chain.head_hash(), Some(&"19".to_string()));
+
+        assert_eq!(
+            chain.iter().copied().collect::<Vec<_>>(),
+            (10..20).rev().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            chain.iter_from_genesis().copied().collect::<Vec<_>>(),
+            (10..20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            chain.range(2..5).copied().collect::<Vec<_>>(),
+            vec![12, 13, 14]
+        );
+        assert_eq!(
+            chain.range(8..).rev().copied().collect::<Vec<_>>(),
+            vec![19, 18]
+        );
+        assert_eq!(chain.range(7..=100).count(), 3);
+        assert_eq!(chain.range(20..30).count(), 0);
+    }
 }
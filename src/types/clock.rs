@@ -0,0 +1,67 @@
+use crate::types::Timestamp;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time, in milliseconds since the Unix epoch.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis()
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time, so a
+/// test can keep a handle and advance the clock a blockchain was given.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<Mutex<Timestamp>>,
+}
+
+impl MockClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, millis: Timestamp) {
+        *self.now.lock().unwrap() += millis;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_is_shared() {
+        let clock = MockClock::new(1_000);
+        let handle = clock.clone();
+
+        handle.advance(500);
+        assert_eq!(clock.now(), 1_500);
+
+        handle.set(10);
+        assert_eq!(clock.now(), 10);
+    }
+}
@@ -0,0 +1,140 @@
+//! Compact ("nBits") encoding of targets as stored in block headers.
+//!
+//! The high byte is the length of the target in bytes and the low three
+//! bytes are its most significant bytes, so a target keeps 24 bits of
+//! precision. Bit 23 would be the sign, so a mantissa that has it set is
+//! shifted down a byte instead.
+
+use num::{BigInt, One, Signed, Zero};
+
+const SIGN_BIT: u32 = 0x0080_0000;
+const MANTISSA_MASK: u32 = 0x007f_ffff;
+
+/// Easiest meaningful target, met by every hash.
+fn max_target() -> BigInt {
+    BigInt::one() << 256
+}
+
+/// Encodes `target`, keeping its three most significant bytes. Targets above
+/// 2^256 are met by every hash and encode as 2^256; negative targets encode
+/// as zero.
+pub fn target_to_compact(target: &BigInt) -> u32 {
+    if !target.is_positive() {
+        return 0;
+    }
+    let max = max_target();
+    let target = if target > &max { &max } else { target };
+
+    let (_, bytes) = target.to_bytes_be();
+    let mut size = bytes.len() as u32;
+    let mut mantissa = bytes
+        .iter()
+        .take(3)
+        .fold(0u32, |mantissa, byte| mantissa << 8 | *byte as u32);
+    if size < 3 {
+        mantissa <<= 8 * (3 - size);
+    }
+
+    if mantissa & SIGN_BIT != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+
+    size << 24 | mantissa
+}
+
+/// Decodes `bits` into a target. Returns `None` for negative values and for
+/// targets above 2^256.
+pub fn compact_to_target(bits: u32) -> Option<BigInt> {
+    let size = bits >> 24;
+    let mantissa = bits & MANTISSA_MASK;
+
+    if mantissa == 0 {
+        return Some(BigInt::zero());
+    }
+    if bits & SIGN_BIT != 0 {
+        return None;
+    }
+
+    let target = if size <= 3 {
+        BigInt::from(mantissa >> (8 * (3 - size)))
+    } else {
+        BigInt::from(mantissa) << (8 * (size - 3)) as usize
+    };
+
+    if target > max_target() {
+        return None;
+    }
+    Some(target)
+}
+
+/// `target` as it reads back from its compact encoding.
+pub fn round_target(target: &BigInt) -> BigInt {
+    compact_to_target(target_to_compact(target))
+        .expect("target_to_compact only produces valid encodings")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_round_trip() {
+        for bits in [
+            0x0101_0000,
+            0x0112_0000,
+            0x0200_8000,
+            0x0312_3456,
+            0x0400_8000,
+            0x1d00_ffff,
+            0x1f05_5f7e,
+            0x2100_8000,
+            0x2101_0000,
+        ] {
+            let target = compact_to_target(bits).unwrap();
+            assert_eq!(target_to_compact(&target), bits, "{:#x}", bits);
+        }
+
+        let target =
+            BigInt::from_str("161578008857017275969393492955354620126364423170461532160").unwrap();
+        let bits = target_to_compact(&target);
+        assert_eq!(bits, 0x1806_96f4);
+        assert_eq!(compact_to_target(bits).unwrap(), target);
+    }
+
+    #[test]
+    fn test_small_targets() {
+        assert_eq!(target_to_compact(&BigInt::zero()), 0);
+        assert_eq!(target_to_compact(&BigInt::from(-5)), 0);
+        assert_eq!(target_to_compact(&BigInt::from(0x12)), 0x0112_0000);
+        assert_eq!(target_to_compact(&BigInt::from(0x80)), 0x0200_8000);
+        assert_eq!(target_to_compact(&BigInt::from(0x1234)), 0x0212_3400);
+
+        assert_eq!(compact_to_target(0x0112_0000), Some(BigInt::from(0x12)));
+        assert_eq!(compact_to_target(0x0200_8000), Some(BigInt::from(0x80)));
+        // Bytes below the stated size are dropped.
+        assert_eq!(compact_to_target(0x0112_3456), Some(BigInt::from(0x12)));
+        assert_eq!(compact_to_target(0x0000_0000), Some(BigInt::zero()));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let max = max_target();
+        assert_eq!(target_to_compact(&max), 0x2101_0000);
+        assert_eq!(target_to_compact(&(&max * 1_000)), 0x2101_0000);
+
+        assert_eq!(compact_to_target(0x2101_0000), Some(max));
+        assert_eq!(compact_to_target(0x2101_0001), None);
+        assert_eq!(compact_to_target(0xff12_3456), None);
+        assert_eq!(compact_to_target(0x0492_3456), None);
+    }
+
+    #[test]
+    fn test_round_target() {
+        let target = BigInt::from(0x1234_5678);
+        assert_eq!(round_target(&target), BigInt::from(0x1234_5600));
+        assert_eq!(round_target(&round_target(&target)), round_target(&target));
+        assert!(round_target(&target) <= target);
+    }
+}
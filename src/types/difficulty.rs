@@ -0,0 +1,297 @@
+//! Difficulty adjustment. Each algorithm derives the target the next block
+//! has to meet from the most recent headers of its branch.
+
+use crate::types::{BlockHeader, ConsensusParams, Timestamp};
+use num::{BigInt, FromPrimitive, One};
+use std::cmp::{max, min};
+use std::fmt::Debug;
+
+pub trait DifficultyAlgorithm: Debug + Send + Sync {
+    /// Number of most recent headers `next_target` needs to look at.
+    fn lookback(&self, params: &ConsensusParams) -> usize;
+
+    /// Target of the block following `recent[0]`. `recent` runs backwards
+    /// from the parent and holds up to `lookback` headers, fewer near
+    /// genesis. `genesis` is the first header of the branch.
+    fn next_target(
+        &self,
+        params: &ConsensusParams,
+        genesis: &BlockHeader,
+        recent: &[&BlockHeader],
+    ) -> BigInt;
+}
+
+/// Retargets every `retarget_window` blocks by how long the window actually
+/// took compared to the block interval, clamped by `max_target_change`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedWindow;
+
+impl DifficultyAlgorithm for FixedWindow {
+    fn lookback(&self, params: &ConsensusParams) -> usize {
+        params.retarget_window as usize + 1
+    }
+
+    fn next_target(
+        &self,
+        params: &ConsensusParams,
+        _genesis: &BlockHeader,
+        recent: &[&BlockHeader],
+    ) -> BigInt {
+        let parent = recent[0];
+        let target = &parent.target();
+
+        if parent.block_number == 0 || !parent.block_number.is_multiple_of(params.retarget_window) {
+            return target.clone();
+        }
+
+        let window_start = recent[params.retarget_window as usize];
+        let max_target_change = BigInt::from(params.max_target_change);
+
+        let average_time =
+            BigInt::from_i64(parent.timestamp as i64 - window_start.timestamp as i64).unwrap()
+                / params.retarget_window;
+
+        let mut new_target = target * average_time / BigInt::from(params.block_interval);
+
+        new_target = min(new_target, target * &max_target_change);
+        new_target = max(new_target, target / &max_target_change);
+
+        new_target
+    }
+}
+
+/// Linearly weighted moving average: the mean target of the last `window`
+/// blocks scaled by their solve times, the most recent weighing the most.
+/// Reacts to every block, so hashrate changes are followed within a window.
+#[derive(Debug, Clone, Copy)]
+pub struct Lwma {
+    pub window: usize,
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn lookback(&self, _params: &ConsensusParams) -> usize {
+        self.window + 1
+    }
+
+    fn next_target(
+        &self,
+        params: &ConsensusParams,
+        _genesis: &BlockHeader,
+        recent: &[&BlockHeader],
+    ) -> BigInt {
+        let count = recent.len().saturating_sub(1).min(self.window);
+        if count == 0 {
+            return recent[0].target();
+        }
+
+        let interval = params.block_interval as i128;
+        let mut weighted_time: i128 = 0;
+        let mut target_sum = BigInt::from(0);
+
+        // Oldest first, so the weight grows towards the parent. Solve times
+        // are clamped since timestamps may run backwards.
+        for (weight, pair) in recent[..=count].windows(2).rev().enumerate() {
+            let solve_time = pair[0].timestamp as i128 - pair[1].timestamp as i128;
+            weighted_time += (weight as i128 + 1) * solve_time.clamp(1, 6 * interval);
+            target_sum += pair[0].target();
+        }
+
+        let count = count as i128;
+        target_sum * weighted_time / (interval * count * (count + 1) / 2) / count
+    }
+}
+
+/// Absolutely scheduled exponential rise targeting: the target doubles or
+/// halves for every `half_life` the chain is behind or ahead of the schedule
+/// set by genesis. Only depends on the parent and genesis, so there is no
+/// window to oscillate over.
+#[derive(Debug, Clone, Copy)]
+pub struct Asert {
+    /// Milliseconds, 0 being taken as 1.
+    pub half_life: Timestamp,
+}
+
+impl DifficultyAlgorithm for Asert {
+    fn lookback(&self, _params: &ConsensusParams) -> usize {
+        1
+    }
+
+    fn next_target(
+        &self,
+        params: &ConsensusParams,
+        genesis: &BlockHeader,
+        recent: &[&BlockHeader],
+    ) -> BigInt {
+        let parent = recent[0];
+        let time_delta = parent.timestamp as i128 - genesis.timestamp as i128;
+        let height_delta = (parent.block_number - genesis.block_number) as i128;
+        let schedule_delta = time_delta - params.block_interval as i128 * height_delta;
+
+        // 2^(schedule_delta / half_life) in 16.16 fixed point, the
+        // fractional part approximated by a cubic polynomial.
+        let exponent = schedule_delta * 65536 / self.half_life.max(1) as i128;
+        let shifts = exponent >> 16;
+        let frac = (exponent & 0xffff) as u128;
+        let factor = 65536
+            + ((195_766_423_245_049 * frac
+                + 971_821_376 * frac * frac
+                + 5_127 * frac * frac * frac
+                + (1 << 47))
+                >> 48);
+
+        let mut target = &params.initial_target * BigInt::from(factor);
+        let shifts = shifts - 16;
+        if shifts >= 0 {
+            target <<= shifts as usize;
+        } else {
+            target >>= shifts.unsigned_abs() as usize;
+        }
+
+        target.clamp(BigInt::one(), BigInt::one() << 256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{compact_to_target, target_to_compact};
+
+    /// Mines `blocks` blocks with a hashrate that changes to `hashrate(n)`
+    /// hashes per millisecond, each block taking exactly the expected time
+    /// for its target. Returns the solve times.
+    fn simulate(
+        algorithm: &dyn DifficultyAlgorithm,
+        params: &ConsensusParams,
+        blocks: usize,
+        hashrate: impl Fn(usize) -> u128,
+    ) -> Vec<Timestamp> {
+        let mut headers = vec![BlockHeader {
+            bits: target_to_compact(&params.initial_target),
+            ..Default::default()
+        }];
+        let mut solve_times = vec![];
+
+        for n in 1..=blocks {
+            let recent: Vec<&BlockHeader> = headers
+                .iter()
+                .rev()
+                .take(algorithm.lookback(params))
+                .collect();
+            let bits = target_to_compact(&algorithm.next_target(params, &headers[0], &recent));
+
+            let work = (BigInt::one() << 256) / (compact_to_target(bits).unwrap() + 1);
+            let solve_time: Timestamp = u128::try_from(work / hashrate(n)).unwrap();
+
+            headers.push(BlockHeader {
+                block_number: n as u128,
+                timestamp: headers[n - 1].timestamp + solve_time,
+                bits,
+                ..Default::default()
+            });
+            solve_times.push(solve_time);
+        }
+
+        solve_times
+    }
+
+    /// Average solve time of the last `count` blocks.
+    fn settled(solve_times: &[Timestamp], count: usize) -> Timestamp {
+        solve_times[solve_times.len() - count..]
+            .iter()
+            .sum::<Timestamp>()
+            / count as Timestamp
+    }
+
+    fn assert_converges(algorithm: &dyn DifficultyAlgorithm) {
+        let params = ConsensusParams::default();
+        let interval = params.block_interval;
+        let close = |time: Timestamp| time.abs_diff(interval) * 10 < interval;
+
+        // The initial target is a thousand times too easy for this hashrate,
+        // then the hashrate triples halfway through.
+        let initial_work = (BigInt::one() << 256) / &params.initial_target;
+        let base = u128::try_from(initial_work * 1_000 / interval).unwrap();
+        let solve_times = simulate(algorithm, &params, 400, |n| {
+            if n <= 200 {
+                base
+            } else {
+                base * 3
+            }
+        });
+
+        assert!(
+            close(settled(&solve_times[..200], 50)),
+            "{:?} doesn't converge: {:?}",
+            algorithm,
+            &solve_times[150..200]
+        );
+        assert!(
+            close(settled(&solve_times, 50)),
+            "{:?} doesn't follow the hashrate: {:?}",
+            algorithm,
+            &solve_times[350..]
+        );
+    }
+
+    #[test]
+    fn test_fixed_window_converges() {
+        assert_converges(&FixedWindow);
+    }
+
+    #[test]
+    fn test_lwma_converges() {
+        assert_converges(&Lwma { window: 45 });
+    }
+
+    #[test]
+    fn test_asert_converges() {
+        assert_converges(&Asert {
+            half_life: 1000 * 60 * 10,
+        });
+    }
+
+    #[test]
+    fn test_asert_on_schedule() {
+        let params = ConsensusParams::default();
+        let asert = Asert {
+            half_life: 1000 * 60 * 60,
+        };
+        let genesis = BlockHeader::default();
+
+        let header = |block_number: u128, timestamp: Timestamp| BlockHeader {
+            block_number,
+            timestamp,
+            ..Default::default()
+        };
+        let target = |parent: &BlockHeader| asert.next_target(&params, &genesis, &[parent]);
+
+        let on_schedule = header(100, 100 * params.block_interval);
+        assert_eq!(target(&on_schedule), params.initial_target);
+
+        let one_half_life_late = header(100, 100 * params.block_interval + asert.half_life);
+        assert_eq!(target(&one_half_life_late), &params.initial_target * 2);
+
+        let one_half_life_early = header(100, 100 * params.block_interval - asert.half_life);
+        assert_eq!(target(&one_half_life_early), &params.initial_target / 2);
+    }
+
+    #[test]
+    fn test_asert_zero_half_life() {
+        let params = ConsensusParams::default();
+        let asert = Asert { half_life: 0 };
+        let genesis = BlockHeader::default();
+        let target = |timestamp: Timestamp| {
+            let parent = BlockHeader {
+                block_number: 100,
+                timestamp,
+                ..Default::default()
+            };
+            asert.next_target(&params, &genesis, &[&parent])
+        };
+
+        let on_schedule = 100 * params.block_interval;
+        assert_eq!(target(on_schedule), params.initial_target);
+        assert_eq!(target(on_schedule + 1), &params.initial_target * 2);
+        assert_eq!(target(on_schedule - 1), &params.initial_target / 2);
+    }
+}
@@ -0,0 +1,518 @@
+use crate::types::{AccountId, Balance, Hash, Timestamp};
+use std::{fmt, io};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Block(BlockError),
+    Transaction(TxError),
+    Mempool(MempoolError),
+    Decode(DecodeError),
+    Store(StoreError),
+}
+
+/// A block was rejected or the chain failed validation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockError {
+    InvalidHash {
+        block_number: u128,
+    },
+    InvalidBlockNumber {
+        block_number: u128,
+        expected: u128,
+    },
+    /// The header commits to a target other than the one required at its
+    /// height.
+    InvalidTarget {
+        block_number: u128,
+    },
+    /// The timestamp is below the median time past of the parent.
+    TimestampTooOld {
+        block_number: u128,
+        timestamp: Timestamp,
+        min: Timestamp,
+    },
+    /// The timestamp is beyond local time plus the allowed drift.
+    TimestampTooNew {
+        block_number: u128,
+        timestamp: Timestamp,
+        max: Timestamp,
+    },
+    EmptyBlock {
+        block_number: u128,
+    },
+    TooLarge {
+        block_number: u128,
+        size: usize,
+        max: usize,
+    },
+    MissingCoinbase {
+        block_number: u128,
+    },
+    MisplacedCoinbase {
+        block_number: u128,
+        tx_index: usize,
+    },
+    CoinbaseExceedsReward {
+        block_number: u128,
+        amount: Balance,
+        reward: Balance,
+    },
+    /// The subsidy plus the fees of the block overflow `Balance`.
+    RewardOverflow {
+        block_number: u128,
+    },
+    Transaction {
+        block_number: u128,
+        tx_index: usize,
+        error: TxError,
+    },
+    MissingPrevHash {
+        block_number: u128,
+    },
+    UnexpectedPrevHash {
+        block_number: u128,
+    },
+    PrevHashMismatch {
+        block_number: u128,
+    },
+    /// Re-executing the chain from genesis gives a different value for
+    /// `account_id` than the live state after this block.
+    StateMismatch {
+        block_number: u128,
+        account_id: AccountId,
+    },
+    /// Executing the block gives a state with another root than the one in
+    /// its header.
+    InvalidStateRoot {
+        block_number: u128,
+    },
+    UnknownParent {
+        block_number: u128,
+    },
+    UnknownBlock(Hash),
+    AlreadyKnown(Hash),
+    GenesisAlreadyExists,
+    /// The state store has no undo record for this canonical block.
+    MissingUndo(Hash),
+    /// Reading or writing the block store or the state store failed while
+    /// handling the block.
+    Store {
+        block_number: u128,
+        error: StoreError,
+    },
+}
+
+/// A transaction can't be executed against the current state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxError {
+    Signature(SignatureError),
+    State(StateError),
+    MintOutsideGenesis,
+    InvalidNonce {
+        expected: u128,
+        got: u128,
+    },
+    InsufficientBalance {
+        account_id: AccountId,
+        balance: Balance,
+        required: Balance,
+    },
+    /// Crediting the account would take its balance past `Balance::MAX`.
+    BalanceOverflow {
+        account_id: AccountId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    MissingSignature,
+    MissingSender,
+    UnknownSigner(AccountId),
+    InvalidSignature,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    AccountAlreadyExists(AccountId),
+    AccountNotFound(AccountId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MempoolError {
+    AlreadyKnown(Hash),
+    Unsigned,
+    /// Only transfers are relayed, the other kinds are built by the block
+    /// producer.
+    NotTransfer,
+    /// The sender already has a queued transaction with this nonce.
+    NonceTaken {
+        nonce: u128,
+    },
+    Rejected(TxError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    LengthOverflow,
+    TrailingBytes,
+    InvalidUtf8,
+    UnsupportedVersion(u8),
+    InvalidTag { kind: &'static str, tag: u8 },
+    InvalidPublicKey,
+    InvalidSignature,
+}
+
+/// A block or state store couldn't be read or written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    Io(String),
+    /// The record at `offset` fails its checksum.
+    Corrupt {
+        offset: u64,
+    },
+    Decode(DecodeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Block(error) => error.fmt(f),
+            Error::Transaction(error) => error.fmt(f),
+            Error::Mempool(error) => error.fmt(f),
+            Error::Decode(error) => error.fmt(f),
+            Error::Store(error) => error.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::InvalidHash { block_number } => {
+                write!(f, "Block {} has invalid hash", block_number)
+            }
+            BlockError::InvalidBlockNumber {
+                block_number,
+                expected,
+            } => write!(
+                f,
+                "Block {} has invalid block number, expected {}",
+                block_number, expected
+            ),
+            BlockError::InvalidTarget { block_number } => {
+                write!(f, "Block {} has invalid target", block_number)
+            }
+            BlockError::TimestampTooOld {
+                block_number,
+                timestamp,
+                min,
+            } => write!(
+                f,
+                "Block {} timestamp {} is below the minimum {}",
+                block_number, timestamp, min
+            ),
+            BlockError::TimestampTooNew {
+                block_number,
+                timestamp,
+                max,
+            } => write!(
+                f,
+                "Block {} timestamp {} is above the maximum {}",
+                block_number, timestamp, max
+            ),
+            BlockError::EmptyBlock { block_number } => {
+                write!(f, "Block {} has 0 transactions", block_number)
+            }
+            BlockError::TooLarge {
+                block_number,
+                size,
+                max,
+            } => write!(
+                f,
+                "Block {} is {} bytes, more than the maximum of {}",
+                block_number, size, max
+            ),
+            BlockError::MissingCoinbase { block_number } => write!(
+                f,
+                "Block {} must start with a coinbase transaction",
+                block_number
+            ),
+            BlockError::MisplacedCoinbase {
+                block_number,
+                tx_index,
+            } => write!(
+                f,
+                "Block {} has a coinbase at position {}, it must be the first transaction",
+                block_number, tx_index
+            ),
+            BlockError::CoinbaseExceedsReward {
+                block_number,
+                amount,
+                reward,
+            } => write!(
+                f,
+                "Block {} coinbase amount {} exceeds block reward {}",
+                block_number, amount, reward
+            ),
+            BlockError::RewardOverflow { block_number } => {
+                write!(f, "Block {} reward overflows", block_number)
+            }
+            BlockError::Transaction {
+                block_number,
+                tx_index,
+                error,
+            } => write!(
+                f,
+                "Error during execution of tx {} in block {}: {}",
+                tx_index, block_number, error
+            ),
+            BlockError::MissingPrevHash { block_number } => {
+                write!(f, "Block {} doesn't have prev_hash", block_number)
+            }
+            BlockError::UnexpectedPrevHash { block_number } => {
+                write!(f, "Genesis block {} shouldn't have prev_hash", block_number)
+            }
+            BlockError::PrevHashMismatch { block_number } => write!(
+                f,
+                "Block {} prev_hash doesn't match Block {} hash",
+                block_number,
+                block_number.saturating_sub(1)
+            ),
+            BlockError::StateMismatch {
+                block_number,
+                account_id,
+            } => write!(
+                f,
+                "Account {} diverges from the replayed state after block {}",
+                account_id, block_number
+            ),
+            BlockError::InvalidStateRoot { block_number } => {
+                write!(f, "Block {} has invalid state root", block_number)
+            }
+            BlockError::UnknownParent { block_number } => {
+                write!(f, "Parent of block {} is unknown", block_number)
+            }
+            BlockError::UnknownBlock(hash) => write!(f, "Block {} is unknown", hash),
+            BlockError::AlreadyKnown(hash) => write!(f, "Block {} is already known", hash),
+            BlockError::GenesisAlreadyExists => write!(f, "Genesis block already exists"),
+            BlockError::MissingUndo(hash) => write!(f, "Undo record of block {} is missing", hash),
+            BlockError::Store {
+                block_number,
+                error,
+            } => write!(f, "Storage failed for block {}: {}", block_number, error),
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Signature(error) => write!(f, "Error while verifying signature: {}", error),
+            TxError::State(error) => error.fmt(f),
+            TxError::MintOutsideGenesis => {
+                write!(f, "Initial supply can be minted only in genesis block")
+            }
+            TxError::InvalidNonce { expected, got } => {
+                write!(f, "Invalid nonce: expected {}, got {}", expected, got)
+            }
+            TxError::InsufficientBalance {
+                account_id,
+                balance,
+                required,
+            } => write!(
+                f,
+                "Account {} has {} tokens, {} required",
+                account_id, balance, required
+            ),
+            TxError::BalanceOverflow { account_id } => {
+                write!(f, "Balance of account {} overflows", account_id)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::MissingSignature => write!(f, "msg should be signed"),
+            SignatureError::MissingSender => write!(f, "msg should have sender to sign it"),
+            SignatureError::UnknownSigner(account_id) => {
+                write!(f, "signer {} doesn't exist", account_id)
+            }
+            SignatureError::InvalidSignature => write!(f, "signature doesn't match"),
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::AccountAlreadyExists(account_id) => {
+                write!(f, "Account {} already exists", account_id)
+            }
+            StateError::AccountNotFound(account_id) => {
+                write!(f, "Account {} doesn't exist", account_id)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::AlreadyKnown(hash) => {
+                write!(f, "Transaction {} is already in the pool", hash)
+            }
+            MempoolError::Unsigned => write!(f, "Only signed transactions can be submitted"),
+            MempoolError::NotTransfer => write!(f, "Only transfers can be submitted"),
+            MempoolError::NonceTaken { nonce } => {
+                write!(f, "A transaction with nonce {} is already queued", nonce)
+            }
+            MempoolError::Rejected(error) => write!(f, "Transaction rejected: {}", error),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "Decoding error: unexpected end of input"),
+            DecodeError::LengthOverflow => {
+                write!(f, "Decoding error: length prefix exceeds input")
+            }
+            DecodeError::TrailingBytes => write!(f, "Decoding error: trailing bytes after value"),
+            DecodeError::InvalidUtf8 => write!(f, "Decoding error: string is not valid UTF-8"),
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "Decoding error: unsupported encoding version {}",
+                version
+            ),
+            DecodeError::InvalidTag { kind, tag } => {
+                write!(f, "Decoding error: invalid {} tag {}", kind, tag)
+            }
+            DecodeError::InvalidPublicKey => write!(f, "Decoding error: invalid public key"),
+            DecodeError::InvalidSignature => write!(f, "Decoding error: invalid signature"),
+        }
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(error) => write!(f, "Storage error: {}", error),
+            StoreError::Corrupt { offset } => {
+                write!(f, "Storage error: corrupt record at offset {}", offset)
+            }
+            StoreError::Decode(error) => write!(f, "Storage error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Block(error) => Some(error),
+            Error::Transaction(error) => Some(error),
+            Error::Mempool(error) => Some(error),
+            Error::Decode(error) => Some(error),
+            Error::Store(error) => Some(error),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlockError::Transaction { error, .. } => Some(error),
+            BlockError::Store { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for TxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TxError::Signature(error) => Some(error),
+            TxError::State(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MempoolError::Rejected(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Decode(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+impl std::error::Error for StateError {}
+impl std::error::Error for DecodeError {}
+
+impl From<BlockError> for Error {
+    fn from(error: BlockError) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<TxError> for Error {
+    fn from(error: TxError) -> Self {
+        Error::Transaction(error)
+    }
+}
+
+impl From<MempoolError> for Error {
+    fn from(error: MempoolError) -> Self {
+        Error::Mempool(error)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(error: DecodeError) -> Self {
+        Error::Decode(error)
+    }
+}
+
+impl From<StoreError> for Error {
+    fn from(error: StoreError) -> Self {
+        Error::Store(error)
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(error: io::Error) -> Self {
+        StoreError::Io(error.to_string())
+    }
+}
+
+impl From<DecodeError> for StoreError {
+    fn from(error: DecodeError) -> Self {
+        StoreError::Decode(error)
+    }
+}
+
+impl From<SignatureError> for TxError {
+    fn from(error: SignatureError) -> Self {
+        TxError::Signature(error)
+    }
+}
+
+impl From<StateError> for TxError {
+    fn from(error: StateError) -> Self {
+        TxError::State(error)
+    }
+}
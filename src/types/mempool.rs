@@ -0,0 +1,569 @@
+use crate::traits::{Hashable, WorldState};
+use crate::types::state::StateOverlay;
+use crate::types::{AccountId, Hash, MempoolError, Timestamp, Transaction};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone)]
+struct PoolEntry {
+    tx: Transaction,
+    size: usize,
+    seq: u64,
+    added_at: Timestamp,
+}
+
+impl PoolEntry {
+    /// Higher fee per byte first, then earlier arrival.
+    fn priority_cmp(&self, other: &Self) -> Ordering {
+        let fee_rate = self.tx.fee().saturating_mul(other.size as u128);
+        let other_fee_rate = other.tx.fee().saturating_mul(self.size as u128);
+
+        fee_rate
+            .cmp(&other_fee_rate)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Pending signed transfers, queued per sender in nonce order.
+///
+/// A transaction is only accepted if it executes on top of the current state
+/// and the sender's already queued transactions, so every queue is a gapless
+/// run of nonces starting at the sender's account nonce.
+#[derive(Default, Debug)]
+pub struct Mempool {
+    entries: HashMap<Hash, PoolEntry>,
+    by_sender: HashMap<AccountId, BTreeMap<u128, Hash>>,
+    next_seq: u64,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Validates `tx` and queues it, recording `now` as its arrival time.
+    pub fn insert<S: WorldState>(
+        &mut self,
+        tx: Transaction,
+        state: &S,
+        now: Timestamp,
+    ) -> Result<Hash, MempoolError> {
+        let hash = tx.hash();
+        if self.contains(&hash) {
+            return Err(MempoolError::AlreadyKnown(hash));
+        }
+
+        if !tx.is_transfer() {
+            return Err(MempoolError::NotTransfer);
+        }
+        let sender = match tx.from() {
+            Some(sender) => sender.clone(),
+            None => return Err(MempoolError::Unsigned),
+        };
+        tx.verify_signature(state)
+            .map_err(|error| MempoolError::Rejected(error.into()))?;
+
+        // Queued transactions are never replaced, a second one with the same
+        // nonce would leave the first orphaned.
+        if self
+            .by_sender
+            .get(&sender)
+            .is_some_and(|queue| queue.contains_key(&tx.nonce()))
+        {
+            return Err(MempoolError::NonceTaken { nonce: tx.nonce() });
+        }
+
+        let mut overlay = StateOverlay::new(state);
+        for queued in self.sender_queue(&sender) {
+            queued
+                .execute(&mut overlay, false)
+                .map_err(MempoolError::Rejected)?;
+        }
+        tx.execute(&mut overlay, false)
+            .map_err(MempoolError::Rejected)?;
+
+        self.by_sender
+            .entry(sender)
+            .or_default()
+            .insert(tx.nonce(), hash.clone());
+        self.entries.insert(
+            hash.clone(),
+            PoolEntry {
+                size: tx.size(),
+                tx,
+                seq: self.next_seq,
+                added_at: now,
+            },
+        );
+        self.next_seq += 1;
+
+        Ok(hash)
+    }
+
+    /// Picks transactions that execute in sequence on top of `state` and
+    /// take up at most `max_size` bytes, preferring higher fee per byte
+    /// without ever reordering a sender's nonces.
+    pub fn select<S: WorldState>(&self, state: &S, max_size: usize) -> Vec<Transaction> {
+        let mut overlay = StateOverlay::new(state);
+        let mut queues: Vec<Vec<&PoolEntry>> = self
+            .by_sender
+            .values()
+            .map(|queue| {
+                queue
+                    .values()
+                    .rev()
+                    .map(|hash| &self.entries[hash])
+                    .collect()
+            })
+            .collect();
+        let mut selected = vec![];
+        let mut remaining = max_size;
+
+        loop {
+            let best = queues
+                .iter()
+                .enumerate()
+                .filter_map(|(i, queue)| queue.last().map(|entry| (i, *entry)))
+                .max_by(|(_, a), (_, b)| a.priority_cmp(b));
+
+            let Some((i, _)) = best else {
+                break;
+            };
+
+            let entry = queues[i].pop().unwrap();
+            if entry.size <= remaining && entry.tx.execute(&mut overlay, false).is_ok() {
+                remaining -= entry.size;
+                selected.push(entry.tx.clone());
+            } else {
+                queues[i].clear();
+            }
+        }
+
+        selected
+    }
+
+    /// Drops transactions whose nonce has been used and queues that no
+    /// longer execute against `state`.
+    pub fn remove_stale<S: WorldState>(&mut self, state: &S) {
+        let senders: Vec<AccountId> = self.by_sender.keys().cloned().collect();
+
+        for sender in senders {
+            let account_nonce = state.get_account_by_id(sender.clone()).map(|a| a.nonce);
+            let mut overlay = StateOverlay::new(state);
+            let mut valid = true;
+            let queue = self.by_sender.remove(&sender).unwrap_or_default();
+            let mut kept = BTreeMap::new();
+
+            for (nonce, hash) in queue {
+                if matches!(account_nonce, Some(account_nonce) if nonce < account_nonce) {
+                    self.entries.remove(&hash);
+                    continue;
+                }
+                valid = valid && self.entries[&hash].tx.execute(&mut overlay, false).is_ok();
+                if valid {
+                    kept.insert(nonce, hash);
+                } else {
+                    self.entries.remove(&hash);
+                }
+            }
+
+            if !kept.is_empty() {
+                self.by_sender.insert(sender, kept);
+            }
+        }
+    }
+
+    /// Drops transactions that have waited longer than `max_age`, together
+    /// with the later transactions of their sender that depend on them.
+    pub fn remove_expired(&mut self, now: Timestamp, max_age: Timestamp) {
+        for queue in self.by_sender.values_mut() {
+            let expired = queue
+                .iter()
+                .find(|(_, hash)| now.saturating_sub(self.entries[*hash].added_at) > max_age)
+                .map(|(nonce, _)| *nonce);
+
+            if let Some(nonce) = expired {
+                for (_, hash) in queue.split_off(&nonce) {
+                    self.entries.remove(&hash);
+                }
+            }
+        }
+        self.by_sender.retain(|_, queue| !queue.is_empty());
+    }
+
+    /// Re-adds transactions that left the chain in a reorganisation together
+    /// with the pending ones, dropping whatever is no longer valid. Pending
+    /// transactions keep their arrival time, the others arrive at `now`.
+    pub fn readmit<S: WorldState>(
+        &mut self,
+        transactions: Vec<Transaction>,
+        state: &S,
+        now: Timestamp,
+    ) {
+        let mut candidates: Vec<PoolEntry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        candidates.sort_by_key(|entry| entry.seq);
+        self.by_sender.clear();
+
+        let mut transactions: Vec<(Transaction, Timestamp)> = transactions
+            .into_iter()
+            .map(|tx| (tx, now))
+            .chain(
+                candidates
+                    .into_iter()
+                    .map(|entry| (entry.tx, entry.added_at)),
+            )
+            .collect();
+        // Stable, so arrival order is kept among equal nonces.
+        transactions.sort_by_key(|(tx, _)| tx.nonce());
+
+        for (tx, added_at) in transactions {
+            let _ = self.insert(tx, state, added_at);
+        }
+    }
+
+    fn sender_queue(&self, sender: &AccountId) -> Vec<&Transaction> {
+        self.by_sender
+            .get(sender)
+            .map(|queue| queue.values().map(|hash| &self.entries[hash].tx).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::traits::{Hashable, WorldState};
+    use crate::types::{
+        miner, Blockchain, ConsensusParams, Mempool, MempoolError, MockClock, Transaction,
+        TransactionData, TxError,
+    };
+    use crate::utils;
+    use crate::utils::append_block_with_tx;
+    use ed25519_dalek::Keypair;
+    use std::sync::Arc;
+
+    fn setup() -> (Blockchain, (String, Keypair), (String, Keypair)) {
+        setup_with(Blockchain::new())
+    }
+
+    fn setup_with(mut bc: Blockchain) -> (Blockchain, (String, Keypair), (String, Keypair)) {
+        let (account_alice, alice_keypair) = utils::generate_account_id();
+        let (account_bob, bob_keypair) = utils::generate_account_id();
+
+        assert!(append_block_with_tx(
+            &mut bc,
+            1,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_alice.clone(),
+                        public_key: alice_keypair.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: account_bob.clone(),
+                        public_key: bob_keypair.public,
+                    },
+                    None,
+                ),
+                Transaction::new(
+                    TransactionData::MintInitialSupply {
+                        to: account_alice.clone(),
+                        amount: 1_000,
+                    },
+                    None,
+                ),
+            ],
+        )
+        .is_ok());
+
+        (
+            bc,
+            (account_alice, alice_keypair),
+            (account_bob, bob_keypair),
+        )
+    }
+
+    fn transfer(from: &(String, Keypair), to: &str, amount: u128, nonce: u128) -> Transaction {
+        transfer_with_fee(from, to, amount, 0, nonce)
+    }
+
+    fn transfer_with_fee(
+        from: &(String, Keypair),
+        to: &str,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+    ) -> Transaction {
+        let mut tx = Transaction::new(
+            TransactionData::Transfer {
+                to: to.to_string(),
+                amount,
+                fee,
+            },
+            Some(from.0.clone()),
+        )
+        .with_nonce(nonce);
+        tx.sign(&from.1);
+        tx
+    }
+
+    #[test]
+    fn test_submit_validates() {
+        let (mut bc, alice, bob) = setup();
+
+        let tx = transfer(&alice, &bob.0, 600, 0);
+        assert!(bc.submit_transaction(tx.clone()).is_ok());
+        assert!(bc.submit_transaction(tx).is_err());
+
+        // Nonce must follow the pending transaction.
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 100, 0))
+            .is_err());
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 100, 2))
+            .is_err());
+
+        // Balance is checked against the pending transactions too.
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 500, 1))
+            .is_err());
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 400, 1))
+            .is_ok());
+
+        // Bad signature.
+        let mut forged = transfer(&alice, &bob.0, 100, 0);
+        forged.sign(&bob.1);
+        assert!(bc.submit_transaction(forged).is_err());
+
+        assert_eq!(bc.transaction_pool().len(), 2);
+    }
+
+    #[test]
+    fn test_only_transfers() {
+        let (mut bc, alice, bob) = setup();
+
+        // A coinbase needs no signature, it would mint whatever it claims.
+        let mut coinbase = Transaction::new(
+            TransactionData::Coinbase {
+                to: alice.0.clone(),
+                amount: u128::MAX,
+            },
+            Some(alice.0.clone()),
+        );
+        coinbase.sign(&alice.1);
+        assert_eq!(
+            bc.submit_transaction(coinbase),
+            Err(MempoolError::NotTransfer)
+        );
+
+        let create_account = Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: "carol".to_string(),
+                public_key: bob.1.public,
+            },
+            Some(bob.0.clone()),
+        );
+        assert_eq!(
+            bc.submit_transaction(create_account),
+            Err(MempoolError::NotTransfer)
+        );
+
+        let mut forged = transfer(&alice, &bob.0, 100, 0);
+        forged.sign(&bob.1);
+        assert!(matches!(
+            bc.submit_transaction(forged),
+            Err(MempoolError::Rejected(TxError::Signature(_)))
+        ));
+        assert!(bc.transaction_pool().is_empty());
+    }
+
+    #[test]
+    fn test_nonce_taken() {
+        let (mut bc, alice, bob) = setup();
+
+        let first = transfer(&alice, &bob.0, 100, 0);
+        assert!(bc.submit_transaction(first.clone()).is_ok());
+        assert_eq!(
+            bc.submit_transaction(transfer_with_fee(&alice, &bob.0, 100, 50, 0)),
+            Err(MempoolError::NonceTaken { nonce: 0 })
+        );
+
+        assert_eq!(bc.transaction_pool().len(), 1);
+        assert!(bc.transaction_pool().contains(&first.hash()));
+    }
+
+    #[test]
+    fn test_block_template() {
+        let (mut bc, alice, bob) = setup();
+
+        let first = transfer(&alice, &bob.0, 600, 0);
+        let second = transfer(&alice, &bob.0, 100, 1);
+        let from_bob = transfer(&bob, &alice.0, 300, 0);
+
+        assert!(bc.submit_transaction(first.clone()).is_ok());
+        assert!(bc.submit_transaction(second.clone()).is_ok());
+        // Bob has nothing yet, so this one is rejected.
+        assert!(bc.submit_transaction(from_bob).is_err());
+
+        let mut block = bc.build_block_template(alice.0.clone());
+        let hashes: Vec<_> = block.transactions()[1..]
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        assert_eq!(hashes, vec![first.hash(), second.hash()]);
+        let block_has_coinbase = block.transactions()[0].is_coinbase();
+
+        miner::mine(&mut block, bc.get_latest_target());
+        assert!(bc.append_block(block).is_ok());
+
+        assert!(bc.transaction_pool().is_empty());
+        assert!(block_has_coinbase);
+        assert_eq!(bc.get_account_by_id(bob.0).unwrap().balance, 700);
+    }
+
+    #[test]
+    fn test_stale_entries_evicted() {
+        let (mut bc, alice, bob) = setup();
+
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 100, 0))
+            .is_ok());
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 100, 1))
+            .is_ok());
+
+        // A competing transaction with nonce 0 spends most of the balance, so
+        // the pooled nonce 0 is stale and nonce 1 can no longer be paid for.
+        assert!(append_block_with_tx(&mut bc, 1, vec![transfer(&alice, &bob.0, 950, 0)]).is_ok());
+
+        assert!(bc.transaction_pool().is_empty());
+    }
+
+    #[test]
+    fn test_select_size_limit() {
+        let (mut bc, alice, bob) = setup();
+
+        let first = transfer(&alice, &bob.0, 100, 0);
+        let second = transfer(&alice, &bob.0, 100, 1);
+        assert!(bc.submit_transaction(first.clone()).is_ok());
+        assert!(bc.submit_transaction(second.clone()).is_ok());
+
+        let pool = bc.transaction_pool();
+        assert_eq!(
+            pool.select(&bc, first.size() * 2),
+            vec![first.clone(), second]
+        );
+        assert_eq!(pool.select(&bc, first.size() * 2 - 1), vec![first]);
+        assert!(pool.select(&bc, 0).is_empty());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let (bc, alice, bob) = setup();
+        let mut pool = Mempool::new();
+
+        let first = transfer(&alice, &bob.0, 100, 0);
+        let second = transfer(&alice, &bob.0, 100, 1);
+        assert!(pool.insert(first.clone(), &bc, 1_000).is_ok());
+        assert!(pool.insert(second.clone(), &bc, 5_000).is_ok());
+
+        pool.remove_expired(6_000, 5_000);
+        assert_eq!(pool.len(), 2);
+
+        // The second transfer is still fresh but depends on the first one.
+        pool.remove_expired(6_001, 5_000);
+        assert!(pool.is_empty());
+        assert!(!pool.contains(&second.hash()));
+    }
+
+    #[test]
+    fn test_expiry_param() {
+        let clock = MockClock::new(1_000_000);
+        let (mut bc, alice, bob) = setup_with(
+            Blockchain::new_with_params(ConsensusParams {
+                mempool_expiry: 1_000,
+                ..Default::default()
+            })
+            .with_clock(Arc::new(clock.clone())),
+        );
+
+        assert!(bc
+            .submit_transaction(transfer(&alice, &bob.0, 100, 0))
+            .is_ok());
+        clock.advance(1_000);
+        bc.remove_expired_transactions();
+        assert_eq!(bc.transaction_pool().len(), 1);
+
+        clock.advance(1);
+        bc.remove_expired_transactions();
+        assert!(bc.transaction_pool().is_empty());
+    }
+
+    #[test]
+    fn test_fee_priority() {
+        let (mut bc, alice, bob) = setup();
+
+        let (account_carol, carol_keypair) = utils::generate_account_id();
+        let carol = (account_carol, carol_keypair);
+        assert!(append_block_with_tx(
+            &mut bc,
+            1,
+            vec![
+                Transaction::new(
+                    TransactionData::CreateAccount {
+                        account_id: carol.0.clone(),
+                        public_key: carol.1.public,
+                    },
+                    None,
+                ),
+                transfer(&alice, &bob.0, 300, 0),
+            ],
+        )
+        .is_ok());
+
+        let alice_low = transfer_with_fee(&alice, &carol.0, 100, 1, 1);
+        let alice_high = transfer_with_fee(&alice, &carol.0, 100, 50, 2);
+        let bob_mid = transfer_with_fee(&bob, &carol.0, 100, 10, 0);
+
+        assert!(bc.submit_transaction(alice_low.clone()).is_ok());
+        assert!(bc.submit_transaction(alice_high.clone()).is_ok());
+        assert!(bc.submit_transaction(bob_mid.clone()).is_ok());
+
+        let mut block = bc.build_block_template(carol.0.clone());
+        let hashes: Vec<_> = block.transactions()[1..]
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+
+        // Alice's high fee can't jump ahead of her own lower nonce.
+        assert_eq!(
+            hashes,
+            vec![bob_mid.hash(), alice_low.hash(), alice_high.hash()]
+        );
+
+        miner::mine(&mut block, bc.get_latest_target());
+        assert!(bc.append_block(block).is_ok());
+
+        assert_eq!(bc.get_account_by_id(alice.0).unwrap().balance, 449);
+        assert_eq!(bc.get_account_by_id(bob.0).unwrap().balance, 190);
+        assert_eq!(
+            bc.get_account_by_id(carol.0).unwrap().balance,
+            300 + bc.block_subsidy(2) + 61
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Binary Merkle tree over transaction hashes.
+//!
+//! Leaves and inner nodes are hashed with distinct prefixes so that an inner
+//! node can never be passed off as a transaction. When a level has an odd
+//! number of nodes the last one is promoted to the next level unchanged.
+
+use crate::traits::{Decodable, Encodable};
+use crate::types::{DecodeError, Hash};
+use blake2::digest::FixedOutput;
+use blake2::{Blake2s, Digest};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MerkleSide {
+    Left,
+    Right,
+}
+
+/// Sibling hash on the path from a leaf to the root and the side it is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProofStep {
+    pub hash: Hash,
+    pub side: MerkleSide,
+}
+
+pub type MerkleProof = Vec<MerkleProofStep>;
+
+fn hash_leaf(tx_hash: &Hash) -> Hash {
+    let mut hasher = Blake2s::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(tx_hash);
+    hex::encode(hasher.finalize_fixed())
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Blake2s::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hex::encode(hasher.finalize_fixed())
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn merkle_root(tx_hashes: &[Hash]) -> Hash {
+    if tx_hashes.is_empty() {
+        return hex::encode(Blake2s::new().finalize_fixed());
+    }
+
+    let mut level: Vec<Hash> = tx_hashes.iter().map(hash_leaf).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level.remove(0)
+}
+
+pub fn merkle_proof(tx_hashes: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= tx_hashes.len() {
+        return None;
+    }
+
+    let mut proof = vec![];
+    let mut index = index;
+    let mut level: Vec<Hash> = tx_hashes.iter().map(hash_leaf).collect();
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push(MerkleProofStep {
+                hash: level[sibling].clone(),
+                side: if sibling < index {
+                    MerkleSide::Left
+                } else {
+                    MerkleSide::Right
+                },
+            });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    Some(proof)
+}
+
+pub fn verify_merkle_proof(root: &Hash, tx_hash: &Hash, proof: &MerkleProof) -> bool {
+    let computed = proof
+        .iter()
+        .fold(hash_leaf(tx_hash), |acc, step| match step.side {
+            MerkleSide::Left => hash_node(&step.hash, &acc),
+            MerkleSide::Right => hash_node(&acc, &step.hash),
+        });
+
+    &computed == root
+}
+
+impl Encodable for MerkleProofStep {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let side: u8 = match self.side {
+            MerkleSide::Left => 0,
+            MerkleSide::Right => 1,
+        };
+        side.encode_to(out);
+        self.hash.encode_to(out);
+    }
+}
+
+impl Decodable for MerkleProofStep {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let side = match u8::decode_from(input)? {
+            0 => MerkleSide::Left,
+            1 => MerkleSide::Right,
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "merkle side",
+                    tag,
+                })
+            }
+        };
+        Ok(Self {
+            side,
+            hash: Hash::decode_from(input)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<Hash> {
+        (0..count)
+            .map(|i| hex::encode(Blake2s::digest(&i.to_le_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn test_proofs_verify() {
+        for count in 1..10 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves);
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index).unwrap();
+                assert!(verify_merkle_proof(&root, leaf, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrong_leaf_fails() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2).unwrap();
+
+        assert!(!verify_merkle_proof(&root, &leaves[3], &proof));
+        assert!(merkle_proof(&leaves, 5).is_none());
+    }
+
+    #[test]
+    fn test_proof_round_trip() {
+        let leaves = leaves(3);
+        let proof = merkle_proof(&leaves, 2).unwrap();
+
+        assert_eq!(MerkleProof::decode(&proof.encode()).unwrap(), proof);
+    }
+}
@@ -1,18 +1,371 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-use num::BigInt;
-use crate::traits::Hashable;
-use crate::types::Block;
-
-pub fn mine(block: &mut Block, target: num::BigInt) {
-    for nonce in 0..u128::MAX {
-        block.set_nonce(nonce);
-        if BigInt::parse_bytes(block.hash().as_bytes(), 16).unwrap() < target {
-            break;
+use crate::traits::Encodable;
+use crate::types::{target_to_compact, Block, Clock, SystemClock};
+use blake2::{Blake2s, Digest};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NONCE_SIZE: usize = std::mem::size_of::<u128>();
+/// Attempts a worker makes between updates of the shared counter.
+const ATTEMPTS_BATCH: u64 = 1024;
+/// Shortest interval between progress reports.
+pub const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Work done by a search so far, or in total once it returned.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MiningStats {
+    /// Hashes computed.
+    pub attempts: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    /// Hashes per second.
+    pub fn hashrate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.attempts as f64 / secs,
+            _ => 0.0,
         }
     }
+}
+
+/// Searches for a nonce so that the header hash is below `target`, stamping
+/// the block with the system time.
+pub fn mine(block: &mut Block, target: num::BigInt) -> MiningStats {
+    mine_with_clock(block, target, &SystemClock)
+}
+
+/// Searches for a nonce so that the header hash is below `target`. Only the
+/// header is hashed on each attempt; the timestamp, read from `clock`, and
+/// the target are fixed before the search since both are part of the proof
+/// of work. The search is against the target as committed in compact form,
+/// which may be slightly below `target`. Runs on the calling thread and
+/// reports no progress; use a `Miner` for either.
+pub fn mine_with_clock(block: &mut Block, target: num::BigInt, clock: &dyn Clock) -> MiningStats {
+    let (preimage, target) = prepare(block, target, clock);
+    let started = Instant::now();
+    let attempts = AtomicU64::new(0);
+
+    if let Some(nonce) = search(preimage, &target, 0, 1, &attempts, || false) {
+        block.header.nonce = nonce;
+    }
     block.update_hash();
-    block.timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis() as u128;
-}
\ No newline at end of file
+
+    MiningStats {
+        attempts: attempts.into_inner(),
+        elapsed: started.elapsed(),
+    }
+}
+
+/// Shared flag telling a `Miner` to give up. Clones refer to the same flag,
+/// and once cancelled it stays cancelled, so every block needs a new one.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Miner splitting the nonce space across worker threads: worker `i` tries
+/// every nonce congruent to `i` modulo the number of threads.
+#[derive(Debug, Clone)]
+pub struct Miner {
+    threads: usize,
+    cancel: CancelHandle,
+    progress: Option<(Sender<MiningStats>, Duration)>,
+}
+
+impl Default for Miner {
+    /// One worker per available core.
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+impl Miner {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            cancel: CancelHandle::new(),
+            progress: None,
+        }
+    }
+
+    /// Sends the running totals to `progress` every `interval` while mining,
+    /// at most every `MIN_PROGRESS_INTERVAL`. A receiver that went away is
+    /// ignored.
+    pub fn with_progress(mut self, progress: Sender<MiningStats>, interval: Duration) -> Self {
+        self.progress = Some((progress, interval.max(MIN_PROGRESS_INTERVAL)));
+        self
+    }
+
+    pub fn with_cancel_handle(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Handle that aborts `mine`, e.g. when a new tip arrives.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Same as `mine_with_clock`, using all workers. Returns once any worker
+    /// finds a nonce, or `None` without touching the nonce if the search was
+    /// cancelled first.
+    pub fn mine(
+        &self,
+        block: &mut Block,
+        target: num::BigInt,
+        clock: &dyn Clock,
+    ) -> Option<MiningStats> {
+        let (preimage, target) = prepare(block, target, clock);
+        let step = self.threads as u128;
+        let started = Instant::now();
+        let solved = AtomicBool::new(false);
+        let attempts = AtomicU64::new(0);
+        let stats = |attempts: &AtomicU64| MiningStats {
+            attempts: attempts.load(Ordering::Relaxed),
+            elapsed: started.elapsed(),
+        };
+
+        let found = thread::scope(|scope| {
+            let reporter = thread::current();
+            let workers: Vec<_> = (0..step)
+                .map(|start| {
+                    let preimage = preimage.clone();
+                    let (target, solved, attempts) = (&target, &solved, &attempts);
+                    let stop = || solved.load(Ordering::Relaxed) || self.cancel.is_cancelled();
+                    let reporter = reporter.clone();
+                    scope.spawn(move || {
+                        let nonce = search(preimage, target, start, step, attempts, stop);
+                        solved.store(true, Ordering::Relaxed);
+                        reporter.unpark();
+                        nonce
+                    })
+                })
+                .collect();
+
+            // This thread only reports progress until the workers are done.
+            if let Some((progress, interval)) = &self.progress {
+                let mut next_report = started + *interval;
+                while !workers.iter().all(|worker| worker.is_finished()) {
+                    thread::park_timeout(next_report.saturating_duration_since(Instant::now()));
+                    if Instant::now() >= next_report {
+                        let _ = progress.send(stats(&attempts));
+                        next_report += *interval;
+                    }
+                }
+            }
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().unwrap())
+                .min()
+        });
+
+        let nonce = found?;
+        block.header.nonce = nonce;
+        block.update_hash();
+        Some(stats(&attempts))
+    }
+}
+
+/// Fixes the timestamp and target in the header and returns its encoding
+/// together with the padded target.
+fn prepare(block: &mut Block, target: num::BigInt, clock: &dyn Clock) -> (Vec<u8>, Vec<u8>) {
+    block.header.timestamp = clock.now();
+    block.header.bits = target_to_compact(&target);
+
+    (block.header.encode(), target_bytes(&block.header.target()))
+}
+
+/// Tries `start`, `start + step`, ... until a nonce meets `target` or `stop`
+/// returns true, adding the hashes computed to `attempts` in batches.
+fn search(
+    mut preimage: Vec<u8>,
+    target: &[u8],
+    start: u128,
+    step: u128,
+    attempts: &AtomicU64,
+    stop: impl Fn() -> bool,
+) -> Option<u128> {
+    // The nonce is the last field of the encoded header, so it is patched in
+    // place instead of re-encoding the header on every attempt.
+    let nonce_offset = preimage.len() - NONCE_SIZE;
+    let mut nonce = start;
+    let mut batch = 0;
+
+    let found = loop {
+        if stop() {
+            break None;
+        }
+        preimage[nonce_offset..].copy_from_slice(&nonce.to_le_bytes());
+        batch += 1;
+        if Blake2s::digest(&preimage).as_slice() < target {
+            break Some(nonce);
+        }
+        if batch == ATTEMPTS_BATCH {
+            attempts.fetch_add(batch, Ordering::Relaxed);
+            batch = 0;
+        }
+        match nonce.checked_add(step) {
+            Some(next) => nonce = next,
+            None => break None,
+        }
+    };
+
+    attempts.fetch_add(batch, Ordering::Relaxed);
+    found
+}
+
+/// Big-endian target padded to the digest size, so that comparing digests
+/// byte-wise is the same as comparing them as numbers.
+fn target_bytes(target: &num::BigInt) -> Vec<u8> {
+    let (_, bytes) = target.to_bytes_be();
+    let size = Blake2s::output_size();
+    if bytes.len() > size {
+        return vec![0xff; size + 1];
+    }
+    let mut padded = vec![0; size - bytes.len()];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MockClock, Transaction, TransactionData};
+    use crate::utils;
+    use num::BigInt;
+    use std::sync::mpsc;
+
+    fn block() -> Block {
+        let mut block = Block::new(None, None);
+        let (account_id, keypair) = utils::generate_account_id();
+        block.add_transaction(Transaction::new(
+            TransactionData::CreateAccount {
+                account_id,
+                public_key: keypair.public,
+            },
+            None,
+        ));
+        block
+    }
+
+    #[test]
+    fn test_parallel_mine() {
+        let target = BigInt::from(2).pow(244);
+        let mut block = block();
+
+        let miner = Miner::new(4);
+        for _ in 0..3 {
+            let stats = miner
+                .mine(&mut block, target.clone(), &MockClock::new(1_000))
+                .unwrap();
+            assert_eq!(block.header.timestamp, 1_000);
+            assert!(block.verify(target.clone()));
+            assert!(stats.attempts > 0);
+            block.add_transaction(block.transactions()[0].clone());
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut block = block();
+        let stats = mine_with_clock(&mut block, BigInt::from(2).pow(248), &SystemClock);
+        assert_eq!(stats.attempts, block.header.nonce as u64 + 1);
+
+        let stats = MiningStats {
+            attempts: 3_000,
+            elapsed: Duration::from_millis(1_500),
+        };
+        assert_eq!(stats.hashrate(), 2_000.0);
+        assert_eq!(MiningStats::default().hashrate(), 0.0);
+    }
+
+    #[test]
+    fn test_progress() {
+        let (sender, receiver) = mpsc::channel();
+        let miner = Miner::new(2).with_progress(sender, Duration::from_millis(10));
+        let cancel = miner.cancel_handle();
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+        assert!(miner
+            .mine(&mut block(), BigInt::from(0), &SystemClock)
+            .is_none());
+        canceller.join().unwrap();
+
+        let reports: Vec<MiningStats> = receiver.try_iter().collect();
+        assert!(reports.len() >= 2);
+        assert!(reports
+            .windows(2)
+            .all(|pair| pair[0].attempts <= pair[1].attempts && pair[0].elapsed < pair[1].elapsed));
+    }
+
+    #[test]
+    fn test_progress_zero_interval() {
+        let (sender, receiver) = mpsc::channel();
+        let miner = Miner::new(2).with_progress(sender, Duration::ZERO);
+        let cancel = miner.cancel_handle();
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+        assert!(miner
+            .mine(&mut block(), BigInt::from(0), &SystemClock)
+            .is_none());
+        canceller.join().unwrap();
+
+        // Reports are paced by the minimum interval instead of flooding.
+        let reports: Vec<MiningStats> = receiver.try_iter().collect();
+        assert!(!reports.is_empty());
+        assert!(reports
+            .iter()
+            .zip(1..)
+            .all(|(report, i)| report.elapsed >= MIN_PROGRESS_INTERVAL * i));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let miner = Miner::new(2);
+        let cancel = miner.cancel_handle();
+        let mut block = block();
+
+        // No hash is below a zero target, so only cancelling ends the search.
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        assert!(miner
+            .mine(&mut block, BigInt::from(0), &SystemClock)
+            .is_none());
+        canceller.join().unwrap();
+
+        let nonce = block.header.nonce;
+        assert!(miner
+            .mine(&mut block, BigInt::from(2).pow(256), &SystemClock)
+            .is_none());
+        assert_eq!(block.header.nonce, nonce);
+    }
+}
@@ -1,19 +1,52 @@
 mod account;
 mod block;
+mod block_store;
+mod block_tree;
 mod blockchain;
 mod chain;
+mod clock;
+mod compact;
+mod difficulty;
+mod error;
+mod mempool;
+mod merkle;
+mod params;
+mod record;
+mod reorg;
+mod reward;
+mod sparse_merkle;
+mod state;
+mod state_store;
 mod transaction;
 pub(crate) mod miner;
 
 pub use account::{Account, AccountType};
-pub use block::Block;
+pub use block::{Block, BlockBody, BlockHeader};
+pub use block_store::{BlockStore, FileBlockStore, MemoryBlockStore};
+pub use block_tree::{block_work, BlockTree, BlockTreeEntry};
 pub use blockchain::Blockchain;
 pub use chain::Chain;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use compact::{compact_to_target, round_target, target_to_compact};
+pub use difficulty::{Asert, DifficultyAlgorithm, FixedWindow, Lwma};
+pub use error::{
+    BlockError, DecodeError, Error, MempoolError, SignatureError, StateError, StoreError,
+    TxError,
+};
+pub use mempool::Mempool;
+pub use params::ConsensusParams;
+pub use reorg::{BlockUndo, Reorg};
+pub use state::{AccountChange, JournaledState};
+pub use state_store::{FileStateStore, MemoryStateStore, StateBatch, StateEntry, StateStore};
+pub use reward::RewardSchedule;
+pub use merkle::{verify_merkle_proof, MerkleProof, MerkleProofStep, MerkleSide};
+pub use sparse_merkle::{verify_account_proof, AccountProof};
 pub use transaction::{Transaction, TransactionData};
-pub use miner::{mine};
+pub use miner::{
+    mine, mine_with_clock, CancelHandle, Miner, MiningStats, MIN_PROGRESS_INTERVAL,
+};
 
 pub type Hash = String;
 pub type Timestamp = u128;
 pub type Balance = u128;
-pub type Error = String;
 pub type AccountId = String;
@@ -0,0 +1,81 @@
+use crate::types::{DifficultyAlgorithm, FixedWindow, RewardSchedule, Timestamp};
+use num::BigInt;
+use std::sync::Arc;
+
+/// Rules every node of a network has to agree on.
+#[derive(Debug, Clone)]
+pub struct ConsensusParams {
+    /// Expected time between two blocks, in milliseconds.
+    pub block_interval: Timestamp,
+    pub difficulty_algorithm: Arc<dyn DifficultyAlgorithm>,
+    /// Number of blocks between two retargets of `FixedWindow`.
+    pub retarget_window: u128,
+    /// Largest factor by which a `FixedWindow` retarget may raise or lower
+    /// the target. A factor of 1 keeps the difficulty fixed.
+    pub max_target_change: u32,
+    /// Target of the genesis block and of the blocks before the first
+    /// retarget.
+    pub initial_target: BigInt,
+    /// Largest encoded size of a block, in bytes.
+    pub max_block_size: usize,
+    pub reward_schedule: RewardSchedule,
+    /// Number of blocks whose median timestamp a new block may not precede.
+    pub median_time_span: usize,
+    /// How far ahead of the local clock a block timestamp may be, in
+    /// milliseconds.
+    pub max_future_drift: Timestamp,
+    /// How long a transaction may wait in the pool before it is dropped, in
+    /// milliseconds. Local policy rather than consensus, but tuned per
+    /// network like the rest.
+    pub mempool_expiry: Timestamp,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+impl ConsensusParams {
+    pub fn mainnet() -> Self {
+        Self {
+            block_interval: 1000 * 60, // 1 min
+            difficulty_algorithm: Arc::new(FixedWindow),
+            retarget_window: 2,
+            max_target_change: 10, // x0.10 or x10
+            initial_target: BigInt::from(5) * BigInt::from(10).pow(74),
+            max_block_size: 1_000_000,
+            reward_schedule: RewardSchedule::default(),
+            median_time_span: 11,
+            max_future_drift: 1000 * 60 * 60 * 2, // 2 hours
+            mempool_expiry: 1000 * 60 * 60 * 24,  // 1 day
+        }
+    }
+
+    /// Like mainnet, with ten times easier initial difficulty and faster
+    /// halvings.
+    pub fn testnet() -> Self {
+        Self {
+            initial_target: BigInt::from(5) * BigInt::from(10).pow(75),
+            reward_schedule: RewardSchedule {
+                halving_interval: 10_000,
+                ..Default::default()
+            },
+            ..Self::mainnet()
+        }
+    }
+
+    /// Fixed, trivial difficulty for local testing: every other hash is a
+    /// valid proof of work.
+    pub fn regtest() -> Self {
+        Self {
+            max_target_change: 1,
+            initial_target: BigInt::from(1) << 255,
+            reward_schedule: RewardSchedule {
+                halving_interval: 150,
+                ..Default::default()
+            },
+            ..Self::mainnet()
+        }
+    }
+}
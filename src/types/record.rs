@@ -0,0 +1,77 @@
+//! Framing shared by the file-backed stores: each record is its payload
+//! prefixed with the length as a little-endian u32 and a checksum of the
+//! length, and followed by a checksum of the payload, so a record torn by a
+//! crash or damaged on disk is detected.
+
+use crate::traits::Encodable;
+use crate::types::StoreError;
+use blake2::{Blake2s, Digest};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+const LEN_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+pub(crate) const CHECKSUM_SIZE: u64 = 4;
+/// Bytes before the payload of a record.
+pub(crate) const HEADER_SIZE: u64 = LEN_SIZE + CHECKSUM_SIZE;
+
+/// `payload` framed as a record.
+pub(crate) fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity((HEADER_SIZE + CHECKSUM_SIZE) as usize + payload.len());
+    (payload.len() as u32).encode_to(&mut record);
+    let len_checksum = checksum(&record);
+    record.extend_from_slice(&len_checksum);
+    record.extend_from_slice(payload);
+    record.extend_from_slice(&checksum(payload));
+    record
+}
+
+/// Payload of the record at `offset` and the offset right after it, or
+/// `None` if the record is torn: its header or, going by a length that
+/// passes its checksum, its payload runs past the end of the file, or it is
+/// the last one and fails its checksum. A damaged length or a record
+/// followed by others that fails its checksum was damaged after it was
+/// written and is reported as `StoreError::Corrupt`, so callers never cut
+/// off the records behind it.
+pub(crate) fn read_record(
+    mut file: &File,
+    offset: u64,
+) -> Result<Option<(Vec<u8>, u64)>, StoreError> {
+    let file_len = file.metadata()?.len();
+    if offset + HEADER_SIZE > file_len {
+        return Ok(None);
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut len = [0; LEN_SIZE as usize];
+    file.read_exact(&mut len)?;
+    let mut stored = [0; CHECKSUM_SIZE as usize];
+    file.read_exact(&mut stored)?;
+    if stored != checksum(&len) {
+        return Err(StoreError::Corrupt { offset });
+    }
+    let len = u32::from_le_bytes(len) as u64;
+
+    let next = offset + HEADER_SIZE + len + CHECKSUM_SIZE;
+    if next > file_len {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0; len as usize];
+    file.read_exact(&mut payload)?;
+    let mut stored = [0; CHECKSUM_SIZE as usize];
+    file.read_exact(&mut stored)?;
+    if stored != checksum(&payload) {
+        return match next == file_len {
+            true => Ok(None),
+            false => Err(StoreError::Corrupt { offset }),
+        };
+    }
+
+    Ok(Some((payload, next)))
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_SIZE as usize] {
+    Blake2s::digest(payload)[..CHECKSUM_SIZE as usize]
+        .try_into()
+        .unwrap()
+}
@@ -0,0 +1,64 @@
+use crate::traits::{Decodable, Encodable};
+use crate::types::state::AccountChange;
+use crate::types::{Account, AccountId, DecodeError, Hash, JournaledState, Transaction};
+
+/// Accounts touched by a block as they were before it was executed, so the
+/// block can be disconnected again. `None` marks an account created by the
+/// block.
+#[derive(Debug, Clone, Default)]
+pub struct BlockUndo {
+    accounts: Vec<AccountChange>,
+}
+
+impl BlockUndo {
+    pub fn new(accounts: Vec<AccountChange>) -> Self {
+        Self { accounts }
+    }
+
+    pub fn accounts(&self) -> &[AccountChange] {
+        &self.accounts
+    }
+
+    pub fn revert(&self, state: &mut JournaledState) {
+        for (account_id, account) in &self.accounts {
+            state.set_account(account_id.clone(), account.clone());
+        }
+    }
+}
+
+impl Encodable for BlockUndo {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (self.accounts.len() as u32).encode_to(out);
+        for (account_id, account) in &self.accounts {
+            account_id.encode_to(out);
+            account.encode_to(out);
+        }
+    }
+}
+
+impl Decodable for BlockUndo {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode_from(input)?;
+        let accounts = (0..len)
+            .map(|_| {
+                Ok((
+                    AccountId::decode_from(input)?,
+                    Option::<Account>::decode_from(input)?,
+                ))
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        Ok(Self { accounts })
+    }
+}
+
+/// Result of switching the canonical chain to another branch.
+#[derive(Debug, Clone, Default)]
+pub struct Reorg {
+    /// Blocks removed from the canonical chain, newest first.
+    pub disconnected_blocks: Vec<Hash>,
+    /// Blocks added to the canonical chain, oldest first.
+    pub connected_blocks: Vec<Hash>,
+    /// Non-coinbase transactions of the disconnected blocks that are not
+    /// part of the new branch, in their original order.
+    pub disconnected_transactions: Vec<Transaction>,
+}
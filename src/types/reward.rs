@@ -0,0 +1,48 @@
+use crate::types::Balance;
+
+/// Block subsidy paid through the coinbase transaction, halved every
+/// `halving_interval` blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardSchedule {
+    pub initial_subsidy: Balance,
+    pub halving_interval: u128,
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        Self {
+            initial_subsidy: 1_000,
+            halving_interval: 100_000,
+        }
+    }
+}
+
+impl RewardSchedule {
+    pub fn subsidy(&self, block_number: u128) -> Balance {
+        let halvings = block_number / self.halving_interval.max(1);
+        if halvings >= Balance::BITS as u128 {
+            return 0;
+        }
+        self.initial_subsidy >> halvings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_halving() {
+        let schedule = RewardSchedule {
+            initial_subsidy: 100,
+            halving_interval: 10,
+        };
+
+        assert_eq!(schedule.subsidy(0), 100);
+        assert_eq!(schedule.subsidy(9), 100);
+        assert_eq!(schedule.subsidy(10), 50);
+        assert_eq!(schedule.subsidy(25), 25);
+        assert_eq!(schedule.subsidy(70), 0);
+        assert_eq!(schedule.subsidy(u128::MAX), 0);
+    }
+}
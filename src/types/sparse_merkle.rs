@@ -0,0 +1,486 @@
+//! Sparse Merkle tree over accounts, committing to the whole world state.
+//!
+//! Accounts are placed by the bits of the hash of their id, most significant
+//! first, with `0` going left. A subtree holding a single account is stored
+//! as just its leaf, so the tree is only as deep as needed to tell the
+//! accounts apart, and a subtree without accounts hashes to all zeros.
+//! Leaves and inner nodes are hashed with distinct prefixes, as in the
+//! transaction tree.
+//!
+//! Nodes live in the state store next to the accounts and are updated in
+//! the same batch, one path per changed account.
+
+use crate::codec::take;
+use crate::traits::{Decodable, Encodable};
+use crate::types::{Account, AccountId, DecodeError, Hash, StateBatch, StateStore, StoreError};
+use blake2::digest::FixedOutput;
+use blake2::{Blake2s, Digest};
+use std::collections::HashMap;
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+/// Prefix of the node keys in the state store.
+const NODE_KEY_PREFIX: &[u8] = b"smt/";
+const KEY_SIZE: usize = 32;
+const KEY_BITS: usize = KEY_SIZE * 8;
+
+/// Position of an account in the tree.
+type Key = [u8; KEY_SIZE];
+
+/// Proof that an account has a given value under a state root, or that it
+/// doesn't exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountProof {
+    /// The account as of the root, `None` if it doesn't exist.
+    pub account: Option<Account>,
+    /// For a missing account, the key and value hash of the account whose
+    /// leaf takes up the place it would have, if any.
+    pub other_leaf: Option<(Hash, Hash)>,
+    /// Sibling hashes from the leaf, or the empty subtree, up to the root.
+    pub siblings: Vec<Hash>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Leaf { key: Key, value_hash: Hash },
+    Inner { left: Hash, right: Hash },
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Leaf { key, value_hash } => hash_leaf(key, value_hash),
+            Node::Inner { left, right } => hash_node(left, right),
+        }
+    }
+}
+
+/// Hash of a subtree without accounts.
+pub(crate) fn empty_root() -> Hash {
+    hex::encode([0; 32])
+}
+
+fn key_of(account_id: &AccountId) -> Key {
+    Blake2s::digest(account_id.as_bytes()).into()
+}
+
+fn value_hash(account: &Account) -> Hash {
+    hex::encode(Blake2s::digest(&account.encode()))
+}
+
+fn hash_leaf(key: &Key, value_hash: &Hash) -> Hash {
+    let mut hasher = Blake2s::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(hex::encode(key));
+    hasher.update(value_hash);
+    hex::encode(hasher.finalize_fixed())
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Blake2s::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hex::encode(hasher.finalize_fixed())
+}
+
+/// Whether `key` goes right below `depth`.
+fn bit(key: &Key, depth: usize) -> bool {
+    key[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn node_hash(node: Option<&Node>) -> Hash {
+    node.map_or_else(empty_root, Node::hash)
+}
+
+/// Nodes read from a state store, with writes held back until they are
+/// handed out as a batch.
+struct Tree<'a> {
+    store: &'a dyn StateStore,
+    writes: HashMap<Vec<u8>, Option<Node>>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(store: &'a dyn StateStore) -> Self {
+        Self {
+            store,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Store key of the node at `depth` on the path of `key`.
+    fn node_key(depth: usize, key: &Key) -> Vec<u8> {
+        let mut node_key = NODE_KEY_PREFIX.to_vec();
+        node_key.extend_from_slice(&(depth as u16).to_be_bytes());
+        node_key.extend_from_slice(&key[..depth.div_ceil(8)]);
+        if !depth.is_multiple_of(8) {
+            *node_key.last_mut().unwrap() &= 0xff << (8 - depth % 8);
+        }
+        node_key
+    }
+
+    fn get(&self, depth: usize, key: &Key) -> Result<Option<Node>, StoreError> {
+        let node_key = Self::node_key(depth, key);
+        if let Some(node) = self.writes.get(&node_key) {
+            return Ok(node.clone());
+        }
+        match self.store.get(&node_key)? {
+            Some(value) => Ok(Some(Node::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, depth: usize, key: &Key, node: Option<Node>) {
+        self.writes.insert(Self::node_key(depth, key), node);
+    }
+
+    fn root(&self) -> Result<Hash, StoreError> {
+        Ok(node_hash(self.get(0, &[0; KEY_SIZE])?.as_ref()))
+    }
+
+    /// Sets the leaf of `key` in the subtree at `depth` on its path and
+    /// returns the new top of that subtree.
+    fn insert(&mut self, depth: usize, key: &Key, value_hash: Hash) -> Result<Node, StoreError> {
+        let (left, right) = match self.get(depth, key)? {
+            None => (None, None),
+            Some(Node::Leaf { key: other, .. }) if other == *key => (None, None),
+            Some(leaf @ Node::Leaf { key: other, .. }) => {
+                // The leaf moves one level down to make room for the new one.
+                self.put(depth + 1, &other, Some(leaf.clone()));
+                match bit(&other, depth) {
+                    false => (Some(leaf.hash()), None),
+                    true => (None, Some(leaf.hash())),
+                }
+            }
+            Some(Node::Inner { left, right }) => (Some(left), Some(right)),
+        };
+
+        let node = if left.is_none() && right.is_none() {
+            Node::Leaf {
+                key: *key,
+                value_hash,
+            }
+        } else {
+            let child = self.insert(depth + 1, key, value_hash)?.hash();
+            let (left, right) = (
+                left.unwrap_or_else(empty_root),
+                right.unwrap_or_else(empty_root),
+            );
+            match bit(key, depth) {
+                false => Node::Inner { left: child, right },
+                true => Node::Inner { left, right: child },
+            }
+        };
+        self.put(depth, key, Some(node.clone()));
+        Ok(node)
+    }
+
+    /// Removes the leaf of `key` from the subtree at `depth` on its path and
+    /// returns the new top of that subtree. An inner node left with a single
+    /// leaf below it is replaced by that leaf.
+    fn remove(&mut self, depth: usize, key: &Key) -> Result<Option<Node>, StoreError> {
+        let node = match self.get(depth, key)? {
+            None => return Ok(None),
+            Some(Node::Leaf { key: other, .. }) if other == *key => None,
+            leaf @ Some(Node::Leaf { .. }) => return Ok(leaf),
+            Some(Node::Inner { left, right }) => {
+                let child = self.remove(depth + 1, key)?;
+                let mut sibling_key = *key;
+                sibling_key[depth / 8] ^= 0x80 >> (depth % 8);
+                let sibling = self.get(depth + 1, &sibling_key)?;
+
+                match (child, sibling) {
+                    (Some(leaf @ Node::Leaf { .. }), None) => {
+                        self.put(depth + 1, key, None);
+                        Some(leaf)
+                    }
+                    (None, Some(leaf @ Node::Leaf { .. })) => {
+                        self.put(depth + 1, &sibling_key, None);
+                        Some(leaf)
+                    }
+                    (None, None) => None,
+                    (child, _) => {
+                        let child = node_hash(child.as_ref());
+                        Some(match bit(key, depth) {
+                            false => Node::Inner { left: child, right },
+                            true => Node::Inner { left, right: child },
+                        })
+                    }
+                }
+            }
+        };
+        self.put(depth, key, node.clone());
+        Ok(node)
+    }
+}
+
+/// Root of the tree in `store` once `changes` are applied, and the node
+/// writes that apply them. `None` removes an account.
+pub(crate) fn update<'a>(
+    store: &dyn StateStore,
+    changes: impl IntoIterator<Item = (&'a AccountId, Option<&'a Account>)>,
+) -> Result<(Hash, StateBatch), StoreError> {
+    let mut tree = Tree::new(store);
+    for (account_id, account) in changes {
+        let key = key_of(account_id);
+        match account {
+            Some(account) => {
+                tree.insert(0, &key, value_hash(account))?;
+            }
+            None => {
+                tree.remove(0, &key)?;
+            }
+        }
+    }
+
+    let root = tree.root()?;
+    let batch = tree
+        .writes
+        .into_iter()
+        .map(|(node_key, node)| (node_key, node.map(|node| node.encode())))
+        .collect();
+    Ok((root, batch))
+}
+
+/// Proof for `account_id` against the root of the tree in `store`. The
+/// account itself is looked up with `get_account`.
+pub(crate) fn prove(
+    store: &dyn StateStore,
+    account_id: &AccountId,
+    get_account: impl FnOnce() -> Result<Option<Account>, StoreError>,
+) -> Result<AccountProof, StoreError> {
+    let tree = Tree::new(store);
+    let key = key_of(account_id);
+
+    let mut siblings = vec![];
+    let mut other_leaf = None;
+    for depth in 0..KEY_BITS {
+        match tree.get(depth, &key)? {
+            Some(Node::Inner { left, right }) => {
+                siblings.push(if bit(&key, depth) { left } else { right });
+            }
+            Some(Node::Leaf {
+                key: other,
+                value_hash,
+            }) if other != key => {
+                other_leaf = Some((hex::encode(other), value_hash));
+                break;
+            }
+            _ => break,
+        }
+    }
+    siblings.reverse();
+
+    let account = match other_leaf {
+        Some(_) => None,
+        None => get_account()?,
+    };
+    Ok(AccountProof {
+        account,
+        other_leaf,
+        siblings,
+    })
+}
+
+/// Checks that `proof` shows the value of `account_id` under `root`: the
+/// account in the proof, or that it doesn't exist.
+pub fn verify_account_proof(root: &Hash, account_id: &AccountId, proof: &AccountProof) -> bool {
+    let key = key_of(account_id);
+    let depth = proof.siblings.len();
+    if depth >= KEY_BITS {
+        return false;
+    }
+
+    let bottom = match (&proof.account, &proof.other_leaf) {
+        (Some(account), None) => hash_leaf(&key, &value_hash(account)),
+        (None, None) => empty_root(),
+        (None, Some((other, value_hash))) => {
+            let other: Key = match hex::decode(other)
+                .ok()
+                .and_then(|other| other.try_into().ok())
+            {
+                Some(other) => other,
+                None => return false,
+            };
+            // The other leaf has to sit on the path of `key`.
+            if other == key || (0..depth).any(|depth| bit(&other, depth) != bit(&key, depth)) {
+                return false;
+            }
+            hash_leaf(&other, value_hash)
+        }
+        (Some(_), Some(_)) => return false,
+    };
+
+    let computed =
+        proof
+            .siblings
+            .iter()
+            .enumerate()
+            .fold(bottom, |acc, (height, sibling)| {
+                match bit(&key, depth - 1 - height) {
+                    false => hash_node(&acc, sibling),
+                    true => hash_node(sibling, &acc),
+                }
+            });
+
+    &computed == root
+}
+
+impl Encodable for Node {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Node::Leaf { key, value_hash } => {
+                0u8.encode_to(out);
+                out.extend_from_slice(key);
+                value_hash.encode_to(out);
+            }
+            Node::Inner { left, right } => {
+                1u8.encode_to(out);
+                left.encode_to(out);
+                right.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decodable for Node {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(Node::Leaf {
+                key: take(input, KEY_SIZE)?.try_into().unwrap(),
+                value_hash: Hash::decode_from(input)?,
+            }),
+            1 => Ok(Node::Inner {
+                left: Hash::decode_from(input)?,
+                right: Hash::decode_from(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag {
+                kind: "tree node",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encodable for AccountProof {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.account.encode_to(out);
+        u8::from(self.other_leaf.is_some()).encode_to(out);
+        if let Some((key, value_hash)) = &self.other_leaf {
+            key.encode_to(out);
+            value_hash.encode_to(out);
+        }
+        self.siblings.encode_to(out);
+    }
+}
+
+impl Decodable for AccountProof {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let account = Option::<Account>::decode_from(input)?;
+        let other_leaf = match u8::decode_from(input)? {
+            0 => None,
+            1 => Some((Hash::decode_from(input)?, Hash::decode_from(input)?)),
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "option",
+                    tag,
+                })
+            }
+        };
+        Ok(Self {
+            account,
+            other_leaf,
+            siblings: Vec::<Hash>::decode_from(input)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountType, MemoryStateStore};
+    use crate::utils;
+
+    fn accounts(count: usize) -> Vec<(AccountId, Account)> {
+        (0..count)
+            .map(|i| {
+                let (account_id, keypair) = utils::generate_account_id();
+                let mut account = Account::new(AccountType::User, keypair.public);
+                account.balance = i as u128;
+                (account_id, account)
+            })
+            .collect()
+    }
+
+    /// Applies `changes` one account per batch and returns the new root.
+    fn apply(store: &mut MemoryStateStore, changes: &[(&AccountId, Option<&Account>)]) -> Hash {
+        let mut root = store_root(store);
+        for change in changes {
+            let (new_root, batch) = update(store, [*change]).unwrap();
+            store.write(batch).unwrap();
+            root = new_root;
+        }
+        assert_eq!(root, store_root(store));
+        root
+    }
+
+    fn store_root(store: &MemoryStateStore) -> Hash {
+        update(store, []).unwrap().0
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let accounts = accounts(20);
+        let changes: Vec<_> = accounts.iter().map(|(id, a)| (id, Some(a))).collect();
+
+        let mut forward = MemoryStateStore::new();
+        let root = apply(&mut forward, &changes);
+        assert_ne!(root, empty_root());
+
+        let mut backward = MemoryStateStore::new();
+        let reversed: Vec<_> = changes.iter().rev().cloned().collect();
+        assert_eq!(apply(&mut backward, &reversed), root);
+        assert_eq!(update(&MemoryStateStore::new(), changes).unwrap().0, root);
+
+        // Removing the accounts in another order collapses the tree back to
+        // nothing.
+        let removals: Vec<_> = accounts
+            .iter()
+            .step_by(2)
+            .chain(accounts.iter().skip(1).step_by(2))
+            .map(|(id, _)| (id, None))
+            .collect();
+        assert_eq!(apply(&mut forward, &removals), empty_root());
+        assert!(forward.scan(NODE_KEY_PREFIX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_account_proofs() {
+        let accounts = accounts(10);
+        let mut store = MemoryStateStore::new();
+        let changes: Vec<_> = accounts.iter().map(|(id, a)| (id, Some(a))).collect();
+        let root = apply(&mut store, &changes);
+
+        for (account_id, account) in &accounts {
+            let proof = prove(&store, account_id, || Ok(Some(account.clone()))).unwrap();
+            assert_eq!(proof.account.as_ref(), Some(account));
+            assert!(verify_account_proof(&root, account_id, &proof));
+            assert_eq!(AccountProof::decode(&proof.encode()).unwrap(), proof);
+
+            let mut forged = proof.clone();
+            forged.account.as_mut().unwrap().balance += 1;
+            assert!(!verify_account_proof(&root, account_id, &forged));
+            assert!(!verify_account_proof(&empty_root(), account_id, &proof));
+        }
+
+        for (account_id, _) in self::accounts(10) {
+            let proof = prove(&store, &account_id, || Ok(None)).unwrap();
+            assert!(proof.account.is_none());
+            assert!(verify_account_proof(&root, &account_id, &proof));
+
+            // An existing account can't be hidden behind the leaf of another.
+            let (existing, _) = &accounts[0];
+            assert!(!verify_account_proof(&root, existing, &proof));
+        }
+    }
+}
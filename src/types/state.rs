@@ -0,0 +1,424 @@
+use crate::traits::{Decodable, Encodable, WorldState};
+use crate::types::sparse_merkle::{self, AccountProof};
+use crate::types::{
+    Account, AccountId, AccountType, DecodeError, Hash, MemoryStateStore, StateBatch, StateError,
+    StateStore, StoreError,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Previous value of an account, `None` if it didn't exist.
+pub type AccountChange = (AccountId, Option<Account>);
+
+/// Prefix of the account keys in the state store.
+const ACCOUNT_PREFIX: &[u8] = b"account/";
+
+/// Account state with checkpoints. While a checkpoint is open every account
+/// about to be created or modified has its previous value recorded, so the
+/// changes can be reverted or handed out as an undo record in time
+/// proportional to the number of touched accounts.
+///
+/// The accounts live in a `StateStore`. Only the accounts changed or
+/// preloaded since the last `flush` are held in memory, the others are read
+/// from the store when needed. `WorldState` can't report errors, so callers
+/// that need reads to be reliable, like block execution, `preload` the
+/// accounts first; any other read that fails returns no account.
+#[derive(Debug)]
+pub struct JournaledState {
+    store: Box<dyn StateStore>,
+    /// Accounts changed since the last flush, `None` for removed ones.
+    dirty: HashMap<AccountId, Option<Account>>,
+    /// Unchanged accounts read by `preload` since the last flush.
+    preloaded: HashMap<AccountId, Option<Account>>,
+    journal: Vec<AccountChange>,
+    checkpoints: Vec<usize>,
+}
+
+impl Default for JournaledState {
+    fn default() -> Self {
+        Self::with_store(Box::new(MemoryStateStore::new()))
+    }
+}
+
+impl JournaledState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_store(store: Box<dyn StateStore>) -> Self {
+        Self {
+            store,
+            dirty: HashMap::new(),
+            preloaded: HashMap::new(),
+            journal: vec![],
+            checkpoints: vec![],
+        }
+    }
+
+    pub fn store(&self) -> &dyn StateStore {
+        self.store.as_ref()
+    }
+
+    /// Every account, sorted by id. Reads the whole state into memory, so
+    /// it is meant for tests and tooling rather than a running node.
+    pub fn accounts(&self) -> Result<Vec<(AccountId, Account)>, StoreError> {
+        let mut accounts: HashMap<AccountId, Account> = HashMap::new();
+        for (key, value) in self.store.scan(ACCOUNT_PREFIX)? {
+            let account_id = String::from_utf8(key[ACCOUNT_PREFIX.len()..].to_vec())
+                .map_err(|_| DecodeError::InvalidUtf8)?;
+            accounts.insert(account_id, Account::decode(&value)?);
+        }
+        for (account_id, account) in &self.dirty {
+            match account {
+                Some(account) => accounts.insert(account_id.clone(), account.clone()),
+                None => accounts.remove(account_id),
+            };
+        }
+
+        let mut accounts: Vec<_> = accounts.into_iter().collect();
+        accounts.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(accounts)
+    }
+
+    /// The account `account_id`, reporting a store that fails to read.
+    pub fn account(&self, account_id: &AccountId) -> Result<Option<Account>, StoreError> {
+        if let Some(account) = self
+            .dirty
+            .get(account_id)
+            .or_else(|| self.preloaded.get(account_id))
+        {
+            return Ok(account.clone());
+        }
+        match self.store.get(&account_key(account_id))? {
+            Some(value) => Ok(Some(Account::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads `account_ids` from the store ahead of a `WorldState` use, so
+    /// that use can't run into a read error. They are kept until the next
+    /// flush.
+    pub fn preload<'a>(
+        &mut self,
+        account_ids: impl IntoIterator<Item = &'a AccountId>,
+    ) -> Result<(), StoreError> {
+        for account_id in account_ids {
+            if !self.dirty.contains_key(account_id) && !self.preloaded.contains_key(account_id) {
+                let account = self.account(account_id)?;
+                self.preloaded.insert(account_id.clone(), account);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.journal.len());
+    }
+
+    /// Undoes every change made since the last checkpoint and closes it.
+    pub fn revert(&mut self) {
+        let checkpoint = self.checkpoints.pop().expect("no open checkpoint");
+        for (account_id, account) in self.journal.split_off(checkpoint).into_iter().rev() {
+            self.set_account(account_id, account);
+        }
+    }
+
+    /// Closes the last checkpoint, keeping its changes, and returns the
+    /// previous value of every account touched since it was opened. The
+    /// changes stay journaled if an outer checkpoint is still open.
+    pub fn commit(&mut self) -> Vec<AccountChange> {
+        let checkpoint = self.checkpoints.pop().expect("no open checkpoint");
+
+        let mut seen = HashSet::new();
+        let changes = self.journal[checkpoint..]
+            .iter()
+            .filter(|(account_id, _)| seen.insert(account_id.clone()))
+            .cloned()
+            .collect();
+
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
+
+        changes
+    }
+
+    /// Overwrites an account, or removes it when `account` is `None`.
+    pub fn set_account(&mut self, account_id: AccountId, account: Option<Account>) {
+        self.record(&account_id);
+        self.dirty.insert(account_id, account);
+    }
+
+    /// Root of the sparse Merkle tree over all accounts.
+    pub fn state_root(&self) -> Result<Hash, StoreError> {
+        self.state_root_with(&HashMap::new())
+    }
+
+    /// Root of the sparse Merkle tree over all accounts once `changes` are
+    /// applied on top, without applying them.
+    pub(crate) fn state_root_with(
+        &self,
+        changes: &HashMap<AccountId, Option<Account>>,
+    ) -> Result<Hash, StoreError> {
+        let mut merged: HashMap<&AccountId, Option<&Account>> = HashMap::new();
+        for (account_id, account) in self.dirty.iter().chain(changes) {
+            merged.insert(account_id, account.as_ref());
+        }
+        Ok(sparse_merkle::update(self.store.as_ref(), merged)?.0)
+    }
+
+    /// Proof of the value of `account_id` against the state root as of the
+    /// last flush.
+    pub fn account_proof(&self, account_id: &AccountId) -> Result<AccountProof, StoreError> {
+        sparse_merkle::prove(self.store.as_ref(), account_id, || {
+            match self.store.get(&account_key(account_id))? {
+                Some(value) => Ok(Some(Account::decode(&value)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Writes the accounts changed since the last flush to the store, in the
+    /// same batch as `batch` and the state tree nodes they change. No
+    /// checkpoint may be open.
+    pub fn flush(&mut self, mut batch: StateBatch) -> Result<(), StoreError> {
+        assert!(self.checkpoints.is_empty(), "flush with an open checkpoint");
+        let changes = self
+            .dirty
+            .iter()
+            .map(|(account_id, account)| (account_id, account.as_ref()));
+        let (_, nodes) = sparse_merkle::update(self.store.as_ref(), changes)?;
+        batch.extend(nodes);
+        batch.extend(self.dirty.iter().map(|(account_id, account)| {
+            (
+                account_key(account_id),
+                account.as_ref().map(Encodable::encode),
+            )
+        }));
+        self.store.write(batch)?;
+        self.dirty.clear();
+        self.preloaded.clear();
+        Ok(())
+    }
+
+    fn load(&self, account_id: &AccountId) -> Option<Account> {
+        self.account(account_id).unwrap_or(None)
+    }
+
+    fn record(&mut self, account_id: &AccountId) {
+        if !self.checkpoints.is_empty() {
+            let previous = self.load(account_id);
+            self.journal.push((account_id.clone(), previous));
+        }
+    }
+}
+
+fn account_key(account_id: &AccountId) -> Vec<u8> {
+    [ACCOUNT_PREFIX, account_id.as_bytes()].concat()
+}
+
+impl WorldState for JournaledState {
+    fn create_account(
+        &mut self,
+        account_id: AccountId,
+        account_type: AccountType,
+        public_key: ed25519_dalek::PublicKey,
+    ) -> Result<(), StateError> {
+        if self.load(&account_id).is_some() {
+            return Err(StateError::AccountAlreadyExists(account_id));
+        }
+        self.record(&account_id);
+        self.dirty
+            .insert(account_id, Some(Account::new(account_type, public_key)));
+        Ok(())
+    }
+
+    fn get_account_by_id(&self, account_id: AccountId) -> Option<Account> {
+        self.load(&account_id)
+    }
+
+    fn get_account_by_id_mut(&mut self, account_id: AccountId) -> Option<&mut Account> {
+        let account = self.load(&account_id)?;
+        self.record(&account_id);
+        self.dirty
+            .entry(account_id)
+            .or_insert(Some(account))
+            .as_mut()
+    }
+}
+
+/// Copy-on-write view over a `WorldState`, used to try transactions without
+/// touching the underlying state. Accounts are copied in on first write.
+pub(crate) struct StateOverlay<'a, S: WorldState> {
+    base: &'a S,
+    /// Accounts changed in the overlay, `None` for removed ones.
+    accounts: HashMap<AccountId, Option<Account>>,
+}
+
+impl<'a, S: WorldState> StateOverlay<'a, S> {
+    pub fn new(base: &'a S) -> Self {
+        Self {
+            base,
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// Overwrites an account, or removes it when `account` is `None`.
+    pub fn set_account(&mut self, account_id: AccountId, account: Option<Account>) {
+        self.accounts.insert(account_id, account);
+    }
+
+    /// Every account changed in the overlay, `None` for removed ones.
+    pub fn into_changes(self) -> HashMap<AccountId, Option<Account>> {
+        self.accounts
+    }
+}
+
+impl<S: WorldState> WorldState for StateOverlay<'_, S> {
+    fn create_account(
+        &mut self,
+        account_id: AccountId,
+        account_type: AccountType,
+        public_key: ed25519_dalek::PublicKey,
+    ) -> Result<(), StateError> {
+        if self.get_account_by_id(account_id.clone()).is_some() {
+            return Err(StateError::AccountAlreadyExists(account_id));
+        }
+        self.accounts
+            .insert(account_id, Some(Account::new(account_type, public_key)));
+        Ok(())
+    }
+
+    fn get_account_by_id(&self, account_id: AccountId) -> Option<Account> {
+        match self.accounts.get(&account_id) {
+            Some(account) => account.clone(),
+            None => self.base.get_account_by_id(account_id),
+        }
+    }
+
+    fn get_account_by_id_mut(&mut self, account_id: AccountId) -> Option<&mut Account> {
+        if !self.accounts.contains_key(&account_id) {
+            let account = self.base.get_account_by_id(account_id.clone())?;
+            self.accounts.insert(account_id.clone(), Some(account));
+        }
+        self.accounts.get_mut(&account_id)?.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::StateEntry;
+    use crate::utils;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Memory store whose reads fail while `failing` is set.
+    #[derive(Debug, Default)]
+    struct FlakyStateStore {
+        inner: MemoryStateStore,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl StateStore for FlakyStateStore {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(StoreError::Io("read failed".to_string()));
+            }
+            self.inner.get(key)
+        }
+
+        fn write(&mut self, batch: StateBatch) -> Result<(), StoreError> {
+            self.inner.write(batch)
+        }
+
+        fn scan(&self, prefix: &[u8]) -> Result<Vec<StateEntry>, StoreError> {
+            self.inner.scan(prefix)
+        }
+    }
+
+    #[test]
+    fn test_preload() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let mut state = JournaledState::with_store(Box::new(FlakyStateStore {
+            inner: MemoryStateStore::new(),
+            failing: failing.clone(),
+        }));
+        let (alice, alice_keypair) = utils::generate_account_id();
+        let (bob, _) = utils::generate_account_id();
+
+        assert!(state
+            .create_account(alice.clone(), AccountType::User, alice_keypair.public)
+            .is_ok());
+        state.flush(vec![]).unwrap();
+
+        // A failed read is reported by `account` and `preload`, and doesn't
+        // bring down a `WorldState` use.
+        failing.store(true, Ordering::SeqCst);
+        assert!(state.account(&alice).is_err());
+        assert!(state.preload([&alice]).is_err());
+        assert!(state.get_account_by_id(alice.clone()).is_none());
+
+        // Preloaded accounts are served without reading the store.
+        failing.store(false, Ordering::SeqCst);
+        state.preload([&alice, &bob]).unwrap();
+        failing.store(true, Ordering::SeqCst);
+        assert!(state.get_account_by_id(alice.clone()).is_some());
+        assert!(state.get_account_by_id(bob.clone()).is_none());
+        assert_eq!(state.account(&bob), Ok(None));
+        state.get_account_by_id_mut(alice.clone()).unwrap().balance = 10;
+        assert_eq!(state.account(&alice).unwrap().unwrap().balance, 10);
+    }
+
+    #[test]
+    fn test_checkpoint_revert() {
+        let mut state = JournaledState::new();
+        let (alice, alice_keypair) = utils::generate_account_id();
+        let (bob, bob_keypair) = utils::generate_account_id();
+
+        assert!(state
+            .create_account(alice.clone(), AccountType::User, alice_keypair.public)
+            .is_ok());
+
+        state.checkpoint();
+        state.get_account_by_id_mut(alice.clone()).unwrap().balance = 10;
+        state.get_account_by_id_mut(alice.clone()).unwrap().balance = 20;
+        assert!(state
+            .create_account(bob.clone(), AccountType::User, bob_keypair.public)
+            .is_ok());
+        state.revert();
+
+        assert_eq!(state.get_account_by_id(alice).unwrap().balance, 0);
+        assert!(state.get_account_by_id(bob).is_none());
+        assert!(state.journal.is_empty());
+    }
+
+    #[test]
+    fn test_commit_returns_previous_values() {
+        let mut state = JournaledState::new();
+        let (alice, alice_keypair) = utils::generate_account_id();
+        let (bob, bob_keypair) = utils::generate_account_id();
+
+        assert!(state
+            .create_account(alice.clone(), AccountType::User, alice_keypair.public)
+            .is_ok());
+
+        state.checkpoint();
+        state.get_account_by_id_mut(alice.clone()).unwrap().balance = 10;
+
+        state.checkpoint();
+        state.get_account_by_id_mut(alice.clone()).unwrap().balance = 20;
+        assert!(state
+            .create_account(bob.clone(), AccountType::User, bob_keypair.public)
+            .is_ok());
+        let inner = state.commit();
+
+        assert_eq!(inner.len(), 2);
+        assert_eq!(inner[0].1.as_ref().unwrap().balance, 10);
+        assert_eq!(inner[1], (bob.clone(), None));
+
+        // The inner changes are still reverted by the outer checkpoint.
+        state.revert();
+        assert_eq!(state.get_account_by_id(alice).unwrap().balance, 0);
+        assert!(state.get_account_by_id(bob).is_none());
+    }
+}
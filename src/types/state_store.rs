@@ -0,0 +1,500 @@
+//! Storage backends for the world state.
+//!
+//! A state store is a key-value store over opaque bytes. `JournaledState`
+//! keeps the accounts in it, and `Blockchain` keeps next to them what it
+//! needs to reopen on top of the stored state: the undo record of every
+//! canonical block and the tip the state belongs to.
+
+use crate::codec::{decode_version, encode_version, take};
+use crate::traits::{Decodable, Encodable};
+use crate::types::record::{encode_record, read_record, HEADER_SIZE};
+use crate::types::{DecodeError, StoreError};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const LOG_FILE: &str = "state.log";
+const COMPACT_FILE: &str = "state.log.compact";
+/// Log size below which `open` doesn't bother compacting.
+const COMPACT_MIN: u64 = 1 << 20;
+/// Writes per record when compacting, so records stay well below the u32
+/// length limit however many keys there are.
+const COMPACT_BATCH: usize = 4096;
+
+/// Writes applied together, `None` removing the key.
+pub type StateBatch = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Key with its value.
+pub type StateEntry = (Vec<u8>, Vec<u8>);
+
+/// Offset and length of a value in the log.
+type Location = (u64, u32);
+/// Key written by a batch and where its value is within the batch.
+type BatchWrite = (Vec<u8>, Option<Location>);
+
+pub trait StateStore: Debug + Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// Applies every write in `batch`, or none of them if the store fails
+    /// or the process dies halfway.
+    fn write(&mut self, batch: StateBatch) -> Result<(), StoreError>;
+
+    /// Every key starting with `prefix` together with its value, in key
+    /// order.
+    fn scan(&self, prefix: &[u8]) -> Result<Vec<StateEntry>, StoreError>;
+}
+
+/// Keeps the state in memory, for tests and throwaway chains.
+#[derive(Debug, Default)]
+pub struct MemoryStateStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn write(&mut self, batch: StateBatch) -> Result<(), StoreError> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.entries.insert(key, value),
+                None => self.entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn scan(&self, prefix: &[u8]) -> Result<Vec<StateEntry>, StoreError> {
+        Ok(self
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+/// Append-only log of write batches with an in-memory index of the keys.
+///
+/// Every batch is one record of `state.log`, framed like the records of
+/// `FileBlockStore`, so a batch torn by a crash fails its checksum and is
+/// cut off on `open` as a whole. Only the keys and the position of their
+/// latest value are kept in memory; values are read from the log on demand.
+/// Overwritten values stay in the log until it is compacted.
+///
+/// The index keeps every live key with the offset of its value, which is
+/// an account and about two tree nodes per account, so memory grows with
+/// the number of accounts rather than with the size of the log.
+#[derive(Debug)]
+pub struct FileStateStore {
+    dir: PathBuf,
+    /// Locked since reads move the shared file cursor.
+    log: Mutex<File>,
+    /// Where the latest value of every key is.
+    index: HashMap<Vec<u8>, Location>,
+    end: u64,
+    /// Bytes of the keys and values in the index.
+    live: u64,
+    truncated: u64,
+    #[cfg(test)]
+    fail_sync: bool,
+}
+
+impl FileStateStore {
+    /// Opens the store in `dir`, creating it if needed. A torn batch at the
+    /// end of the log is cut off, and the log is compacted if most of it is
+    /// overwritten values.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let mut store = Self::load(dir.as_ref())?;
+        if store.end > COMPACT_MIN && store.end > 2 * store.live {
+            let truncated = store.truncated;
+            store.compact()?;
+            store.truncated = truncated;
+        }
+        Ok(store)
+    }
+
+    /// Bytes cut off the end of the log by `open`.
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+
+    /// Size of the log in bytes.
+    pub fn log_size(&self) -> u64 {
+        self.end
+    }
+
+    /// Rewrites the log with only the latest value of every key. The new log
+    /// is written next to the old one and renamed over it once synced.
+    pub fn compact(&mut self) -> Result<(), StoreError> {
+        let path = self.dir.join(COMPACT_FILE);
+        let mut file = File::create(&path)?;
+
+        let mut keys: Vec<&Vec<u8>> = self.index.keys().collect();
+        keys.sort();
+        for chunk in keys.chunks(COMPACT_BATCH) {
+            let batch = chunk
+                .iter()
+                .map(|key| Ok(((*key).clone(), Some(self.read_value(self.index[*key])?))))
+                .collect::<Result<StateBatch, StoreError>>()?;
+            file.write_all(&encode_record(&encode_batch(&batch)))?;
+        }
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&path, self.dir.join(LOG_FILE))?;
+        *self = Self::load(&self.dir)?;
+        Ok(())
+    }
+
+    fn load(dir: &Path) -> Result<Self, StoreError> {
+        fs::create_dir_all(dir)?;
+        // A compaction that didn't get to the rename leaves a partial copy
+        // behind; the log itself is still complete.
+        match fs::remove_file(dir.join(COMPACT_FILE)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOG_FILE))?;
+        let log_len = log.metadata()?.len();
+
+        let mut store = Self {
+            dir: dir.to_path_buf(),
+            log: Mutex::new(log),
+            index: HashMap::new(),
+            end: 0,
+            live: 0,
+            truncated: 0,
+            #[cfg(test)]
+            fail_sync: false,
+        };
+        while let Some((payload, next)) = read_record(store.log.get_mut().unwrap(), store.end)? {
+            store.index_batch(store.end, &payload)?;
+            store.end = next;
+        }
+
+        let log = store.log.get_mut().unwrap();
+        log.set_len(store.end)?;
+        log.sync_data()?;
+        store.truncated = log_len - store.end;
+        Ok(store)
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<(), StoreError> {
+        let log = self.log.get_mut().unwrap();
+        log.seek(SeekFrom::Start(self.end))?;
+        log.write_all(record)?;
+        #[cfg(test)]
+        if self.fail_sync {
+            return Err(StoreError::Io("sync failed".to_string()));
+        }
+        log.sync_data()?;
+        Ok(())
+    }
+
+    fn read_value(&self, (offset, len): Location) -> Result<Vec<u8>, StoreError> {
+        let log = self.log.lock().unwrap();
+        let mut file: &File = &log;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut value = vec![0; len as usize];
+        file.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    /// Points the index at the values of the batch stored at `offset`.
+    fn index_batch(&mut self, offset: u64, payload: &[u8]) -> Result<(), StoreError> {
+        for (key, value) in decode_batch(payload)? {
+            let key_len = key.len() as u64;
+            let previous = match value {
+                Some((position, len)) => {
+                    self.live += key_len + len as u64;
+                    self.index
+                        .insert(key, (offset + HEADER_SIZE + position, len))
+                }
+                None => self.index.remove(&key),
+            };
+            if let Some((_, len)) = previous {
+                self.live -= key_len + len as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.index
+            .get(key)
+            .map(|location| self.read_value(*location))
+            .transpose()
+    }
+
+    fn write(&mut self, batch: StateBatch) -> Result<(), StoreError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let payload = encode_batch(&batch);
+        let record = encode_record(&payload);
+
+        if let Err(error) = self.append(&record) {
+            // Leave no trace of the batch for `open` to replay.
+            self.log.get_mut().unwrap().set_len(self.end)?;
+            return Err(error);
+        }
+
+        self.index_batch(self.end, &payload)?;
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn scan(&self, prefix: &[u8]) -> Result<Vec<StateEntry>, StoreError> {
+        let mut entries = self
+            .index
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, location)| Ok((key.clone(), self.read_value(*location)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+}
+
+fn encode_batch(batch: &StateBatch) -> Vec<u8> {
+    let mut out = vec![];
+    encode_version(&mut out);
+    (batch.len() as u32).encode_to(&mut out);
+    for (key, value) in batch {
+        key.encode_to(&mut out);
+        value.encode_to(&mut out);
+    }
+    out
+}
+
+/// Keys written by the batch in `payload`, with the position and length of
+/// their value within it, or `None` for removed keys.
+fn decode_batch(payload: &[u8]) -> Result<Vec<BatchWrite>, DecodeError> {
+    let mut input = payload;
+    decode_version(&mut input)?;
+
+    let count = u32::decode_from(&mut input)?;
+    let mut writes = vec![];
+    for _ in 0..count {
+        let key_len = u32::decode_from(&mut input)? as usize;
+        let key = take(&mut input, key_len)?.to_vec();
+        let value = match u8::decode_from(&mut input)? {
+            0 => None,
+            1 => {
+                let len = u32::decode_from(&mut input)?;
+                let position = (payload.len() - input.len()) as u64;
+                take(&mut input, len as usize)?;
+                Some((position, len))
+            }
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "option",
+                    tag,
+                })
+            }
+        };
+        writes.push((key, value));
+    }
+
+    if !input.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block_store::tests::TempDir;
+
+    fn write(store: &mut dyn StateStore, writes: &[(&str, Option<&str>)]) {
+        let batch = writes
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.map(|value| value.into())))
+            .collect();
+        store.write(batch).unwrap();
+    }
+
+    fn get(store: &dyn StateStore, key: &str) -> Option<String> {
+        let value = store.get(key.as_bytes()).unwrap()?;
+        Some(String::from_utf8(value).unwrap())
+    }
+
+    fn keys(store: &dyn StateStore, prefix: &str) -> Vec<String> {
+        store
+            .scan(prefix.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| String::from_utf8(key).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = TempDir::new("state_read_write");
+        let stores: [Box<dyn StateStore>; 2] = [
+            Box::new(MemoryStateStore::new()),
+            Box::new(FileStateStore::open(&dir.0).unwrap()),
+        ];
+
+        for mut store in stores {
+            write(
+                store.as_mut(),
+                &[
+                    ("a/2", Some("two")),
+                    ("a/1", Some("one")),
+                    ("b/1", Some("b")),
+                ],
+            );
+            write(store.as_mut(), &[("a/2", Some("deux")), ("b/1", None)]);
+
+            assert_eq!(get(store.as_ref(), "a/1"), Some("one".to_string()));
+            assert_eq!(get(store.as_ref(), "a/2"), Some("deux".to_string()));
+            assert_eq!(get(store.as_ref(), "b/1"), None);
+            assert_eq!(keys(store.as_ref(), "a/"), vec!["a/1", "a/2"]);
+            assert!(keys(store.as_ref(), "b/").is_empty());
+        }
+    }
+
+    #[test]
+    fn test_reopen_after_torn_write() {
+        let dir = TempDir::new("state_torn_write");
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        write(&mut store, &[("a", Some("1")), ("b", Some("2"))]);
+        write(&mut store, &[("a", None), ("c", Some("3"))]);
+        let intact_len = store.log_size();
+        drop(store);
+
+        // A batch cut short by a crash.
+        let log_path = dir.0.join(LOG_FILE);
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        log.write_all(&100u32.to_le_bytes()).unwrap();
+        log.write_all(&[1, 2, 3]).unwrap();
+        drop(log);
+
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 7);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), intact_len);
+        assert_eq!(keys(&store, ""), vec!["b", "c"]);
+
+        write(&mut store, &[("d", Some("4"))]);
+        drop(store);
+        let store = FileStateStore::open(&dir.0).unwrap();
+        assert_eq!(get(&store, "d"), Some("4".to_string()));
+    }
+
+    #[test]
+    fn test_corrupt_batch() {
+        let dir = TempDir::new("state_corrupt_batch");
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        write(&mut store, &[("a", Some("1"))]);
+        write(&mut store, &[("b", Some("2"))]);
+        let len = store.log_size();
+        drop(store);
+
+        // Damage to a batch that isn't the last one is no torn write, the
+        // batches behind it must not be cut off.
+        let log_path = dir.0.join(LOG_FILE);
+        let mut log = OpenOptions::new().write(true).open(&log_path).unwrap();
+        log.seek(SeekFrom::Start(HEADER_SIZE + 1)).unwrap();
+        log.write_all(&[0xff]).unwrap();
+        drop(log);
+
+        assert!(matches!(
+            FileStateStore::open(&dir.0),
+            Err(StoreError::Corrupt { offset: 0 })
+        ));
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_failed_write() {
+        let dir = TempDir::new("state_failed_write");
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        write(&mut store, &[("a", Some("1"))]);
+        let len = store.log_size();
+
+        // The batch reached the log but couldn't be synced, so it failed as
+        // a whole and must not come back on `open`.
+        store.fail_sync = true;
+        assert!(store
+            .write(vec![(b"b".to_vec(), Some(b"2".to_vec()))])
+            .is_err());
+        assert_eq!(get(&store, "b"), None);
+        let log_path = dir.0.join(LOG_FILE);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), len);
+
+        store.fail_sync = false;
+        write(&mut store, &[("c", Some("3"))]);
+        drop(store);
+        let store = FileStateStore::open(&dir.0).unwrap();
+        assert_eq!(store.truncated(), 0);
+        assert_eq!(keys(&store, ""), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let dir = TempDir::new("state_corrupt_length");
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        write(&mut store, &[("a", Some("1"))]);
+        write(&mut store, &[("b", Some("2"))]);
+        let len = store.log_size();
+        drop(store);
+
+        // A length running past the end of the log, which a torn batch would
+        // have too, but which fails its checksum.
+        let log_path = dir.0.join(LOG_FILE);
+        let mut log = OpenOptions::new().write(true).open(&log_path).unwrap();
+        log.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        drop(log);
+
+        assert!(matches!(
+            FileStateStore::open(&dir.0),
+            Err(StoreError::Corrupt { offset: 0 })
+        ));
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = TempDir::new("state_compact");
+        let mut store = FileStateStore::open(&dir.0).unwrap();
+        for i in 0..100 {
+            write(
+                &mut store,
+                &[("counter", Some(&i.to_string())), ("other", None)],
+            );
+        }
+        write(&mut store, &[("other", Some("value"))]);
+        let size = store.log_size();
+
+        store.compact().unwrap();
+        assert!(store.log_size() < size / 10);
+        assert_eq!(get(&store, "counter"), Some("99".to_string()));
+        assert_eq!(keys(&store, ""), vec!["counter", "other"]);
+        drop(store);
+
+        let store = FileStateStore::open(&dir.0).unwrap();
+        assert_eq!(get(&store, "other"), Some("value".to_string()));
+        assert!(!dir.0.join(COMPACT_FILE).exists());
+    }
+}
@@ -1,10 +1,14 @@
-use crate::traits::{Hashable, WorldState};
-use crate::types::{AccountId, AccountType, Balance, Error, Hash, Timestamp};
+use crate::codec::{decode_version, encode_version};
+use crate::traits::{Decodable, Encodable, Hashable, WorldState};
+use crate::types::{
+    AccountId, AccountType, Balance, DecodeError, Hash, SignatureError, StateError, Timestamp,
+    TxError,
+};
 use blake2::digest::FixedOutput;
 use blake2::{Blake2s, Digest};
-use ed25519_dalek::{Keypair, Signature, Signer, Verifier};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
     nonce: u128,
     timestamp: Timestamp,
@@ -13,7 +17,7 @@ pub struct Transaction {
     signature: Option<Signature>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransactionData {
     CreateAccount {
         account_id: AccountId,
@@ -26,6 +30,14 @@ pub enum TransactionData {
     Transfer {
         to: AccountId,
         amount: Balance,
+        /// Paid by the sender on top of `amount` to the block producer.
+        fee: Balance,
+    },
+    /// Block reward, the first transaction of every block after genesis.
+    /// `amount` is bounded by the block subsidy plus the fees of the block.
+    Coinbase {
+        to: AccountId,
+        amount: Balance,
     },
 }
 
@@ -40,114 +52,291 @@ impl Transaction {
         }
     }
 
-    pub fn verify_signature<T: WorldState>(&self, state: &mut T) -> Result<(), Error> {
-        if !self.signature.is_some() {
-            return Err("Error: msg should be signed".to_string());
-        }
+    /// Sets the sender nonce. Must be called before `sign`, since the nonce
+    /// is covered by the signature.
+    pub fn with_nonce(mut self, nonce: u128) -> Self {
+        self.nonce = nonce;
+        self
+    }
 
-        if !self.from.is_some() {
-            return Err("Error: msg should have sender to sign it".to_string());
-        }
+    pub fn nonce(&self) -> u128 {
+        self.nonce
+    }
+
+    pub fn from(&self) -> Option<&AccountId> {
+        self.from.as_ref()
+    }
 
-        let account = self.from.clone().unwrap().clone();
+    /// Every account executing the transaction may read or write.
+    pub fn accounts(&self) -> Vec<&AccountId> {
+        let account_id = match &self.data {
+            TransactionData::CreateAccount { account_id, .. } => account_id,
+            TransactionData::MintInitialSupply { to, .. }
+            | TransactionData::Transfer { to, .. }
+            | TransactionData::Coinbase { to, .. } => to,
+        };
+        self.from.iter().chain([account_id]).collect()
+    }
 
-        let account = state.get_account_by_id(account);
+    pub fn is_transfer(&self) -> bool {
+        matches!(self.data, TransactionData::Transfer { .. })
+    }
 
-        if account.is_none() {
-            return Err("Error: msg should have sender to sign it".to_string());
+    pub fn is_coinbase(&self) -> bool {
+        matches!(self.data, TransactionData::Coinbase { .. })
+    }
+
+    pub fn fee(&self) -> Balance {
+        match &self.data {
+            TransactionData::Transfer { fee, .. } => *fee,
+            _ => 0,
         }
+    }
+
+    /// Size of the transaction in its wire encoding.
+    pub fn size(&self) -> usize {
+        self.encode().len()
+    }
+
+    pub fn verify_signature<T: WorldState>(&self, state: &T) -> Result<(), SignatureError> {
+        let signature = self.signature.ok_or(SignatureError::MissingSignature)?;
+        let from = self.from.clone().ok_or(SignatureError::MissingSender)?;
 
-        let account = account.unwrap();
+        let account = state
+            .get_account_by_id(from.clone())
+            .ok_or(SignatureError::UnknownSigner(from))?;
 
-        match account
+        account
             .public_key
-            .verify(self.hash().as_bytes(), &self.signature.unwrap())
-        {
-            Ok(()) => Ok(()),
-            Err(_) => Err("Error: error occurred while verifying signature".to_string()),
-        }
+            .verify(self.hash().as_bytes(), &signature)
+            .map_err(|_| SignatureError::InvalidSignature)
     }
 
     pub fn sign(&mut self, keypair: &Keypair) {
         self.signature = Some(keypair.sign(self.hash().as_bytes()));
     }
 
-    pub fn execute<T: WorldState>(&self, state: &mut T, is_genesis: bool) -> Result<(), Error> {
+    pub fn execute<T: WorldState>(&self, state: &mut T, is_genesis: bool) -> Result<(), TxError> {
         //DONE Task 2: Implement signature
         match &self.data {
             TransactionData::CreateAccount {
                 account_id,
                 public_key,
-            } => state.create_account(account_id.clone(), AccountType::User, *public_key),
+            } => Ok(state.create_account(account_id.clone(), AccountType::User, *public_key)?),
             TransactionData::MintInitialSupply { to, amount } => {
                 if !is_genesis {
-                    return Err("Initial supply can be minted only in genesis block.".to_string());
-                }
-                match state.get_account_by_id_mut(to.clone()) {
-                    Some(account) => {
-                        account.balance += amount;
-                        Ok(())
-                    }
-                    None => Err("Invalid account.".to_string()),
+                    return Err(TxError::MintOutsideGenesis);
                 }
+                credit(state, to, *amount)
             }
+            TransactionData::Coinbase { to, amount } => credit(state, to, *amount),
             // DONE Task 1: Implement transfer transition function
             // 1. Check that receiver and sender accounts exist
             // 2. Check sender balance
             // 3. Change sender/receiver balances and save to state
             // 4. Test
-            TransactionData::Transfer { to, amount } => {
-                let from = self.from.clone();
+            TransactionData::Transfer { to, amount, fee } => {
+                let from = self.from.clone().ok_or(SignatureError::MissingSender)?;
 
-                if !from.is_some() {
-                    return Err("You can't make transfer from non-existing account".to_string());
+                if state.get_account_by_id_mut(from.clone()).is_none() {
+                    return Err(StateError::AccountNotFound(from).into());
                 }
-
-                let from = from.unwrap();
-
-                if !state.get_account_by_id_mut(from.clone()).is_some() {
-                    return Err("You can't make transfer from non-existing account".to_string());
-                }
-                if !state.get_account_by_id_mut(to.clone()).is_some() {
-                    return Err("You can't make transfer to non-existing account".to_string());
+                if state.get_account_by_id_mut(to.clone()).is_none() {
+                    return Err(StateError::AccountNotFound(to.clone()).into());
                 };
 
-                if let Err(e) = self.verify_signature(state) {
-                    return Err(format!("Error while verifying signature: {}", e));
-                }
+                self.verify_signature(state)?;
 
-                let from = state.get_account_by_id_mut(from.clone()).unwrap();
+                let sender = state.get_account_by_id_mut(from.clone()).unwrap();
 
-                if &from.balance < amount {
-                    return Err("You can't transfer more tokens than you have".to_string());
+                if sender.nonce != self.nonce {
+                    return Err(TxError::InvalidNonce {
+                        expected: sender.nonce,
+                        got: self.nonce,
+                    });
                 }
 
-                from.balance -= amount;
-
-                let to = state.get_account_by_id_mut(to.clone()).unwrap();
+                match amount.checked_add(*fee) {
+                    Some(total) if total <= sender.balance => sender.balance -= total,
+                    total => {
+                        return Err(TxError::InsufficientBalance {
+                            account_id: from,
+                            balance: sender.balance,
+                            required: total.unwrap_or(Balance::MAX),
+                        });
+                    }
+                }
 
-                to.balance += amount;
+                sender.nonce += 1;
 
-                Ok(())
+                credit(state, to, *amount)
             }
         }
     }
 }
 
+/// Adds `amount` to the balance of `account_id`.
+fn credit<T: WorldState>(
+    state: &mut T,
+    account_id: &AccountId,
+    amount: Balance,
+) -> Result<(), TxError> {
+    let account = state
+        .get_account_by_id_mut(account_id.clone())
+        .ok_or_else(|| StateError::AccountNotFound(account_id.clone()))?;
+    account.balance =
+        account
+            .balance
+            .checked_add(amount)
+            .ok_or_else(|| TxError::BalanceOverflow {
+                account_id: account_id.clone(),
+            })?;
+    Ok(())
+}
+
+impl Transaction {
+    /// Canonical encoding of everything covered by the signature.
+    fn encode_unsigned(&self, out: &mut Vec<u8>) {
+        encode_version(out);
+        self.nonce.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.from.encode_to(out);
+        self.data.encode_to(out);
+    }
+}
+
 impl Hashable for Transaction {
     fn hash(&self) -> Hash {
-        let mut hasher = Blake2s::new();
+        let mut unsigned = vec![];
+        self.encode_unsigned(&mut unsigned);
 
-        hasher.update(format!(
-            "{:?}",
-            (
-                self.nonce,
-                self.timestamp,
-                self.from.clone(),
-                self.data.clone()
-            )
-        ));
+        let mut hasher = Blake2s::new();
+        hasher.update(unsigned);
 
         hex::encode(hasher.finalize_fixed())
     }
 }
+
+impl Encodable for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.encode_unsigned(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode_version(input)?;
+        Ok(Self {
+            nonce: u128::decode_from(input)?,
+            timestamp: Timestamp::decode_from(input)?,
+            from: Option::<AccountId>::decode_from(input)?,
+            data: TransactionData::decode_from(input)?,
+            signature: Option::<Signature>::decode_from(input)?,
+        })
+    }
+}
+
+impl Encodable for TransactionData {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            TransactionData::CreateAccount {
+                account_id,
+                public_key,
+            } => {
+                0u8.encode_to(out);
+                account_id.encode_to(out);
+                public_key.encode_to(out);
+            }
+            TransactionData::MintInitialSupply { to, amount } => {
+                1u8.encode_to(out);
+                to.encode_to(out);
+                amount.encode_to(out);
+            }
+            TransactionData::Transfer { to, amount, fee } => {
+                2u8.encode_to(out);
+                to.encode_to(out);
+                amount.encode_to(out);
+                fee.encode_to(out);
+            }
+            TransactionData::Coinbase { to, amount } => {
+                3u8.encode_to(out);
+                to.encode_to(out);
+                amount.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decodable for TransactionData {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(TransactionData::CreateAccount {
+                account_id: AccountId::decode_from(input)?,
+                public_key: PublicKey::decode_from(input)?,
+            }),
+            1 => Ok(TransactionData::MintInitialSupply {
+                to: AccountId::decode_from(input)?,
+                amount: Balance::decode_from(input)?,
+            }),
+            2 => Ok(TransactionData::Transfer {
+                to: AccountId::decode_from(input)?,
+                amount: Balance::decode_from(input)?,
+                fee: Balance::decode_from(input)?,
+            }),
+            3 => Ok(TransactionData::Coinbase {
+                to: AccountId::decode_from(input)?,
+                amount: Balance::decode_from(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag {
+                kind: "transaction type",
+                tag,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+        let (account_bob, _) = utils::generate_account_id();
+
+        let mut tx = Transaction::new(
+            TransactionData::Transfer {
+                to: account_bob,
+                amount: 100,
+                fee: 1,
+            },
+            Some(account_alice),
+        )
+        .with_nonce(3);
+        tx.sign(&keypair_alice);
+
+        let decoded = Transaction::decode(&tx.encode()).unwrap();
+
+        assert_eq!(decoded, tx);
+        assert_eq!(decoded.hash(), tx.hash());
+    }
+
+    #[test]
+    fn test_signature_not_in_hash() {
+        let (account_alice, keypair_alice) = utils::generate_account_id();
+
+        let mut tx = Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account_alice,
+                public_key: keypair_alice.public,
+            },
+            None,
+        );
+        let hash = tx.hash();
+        tx.sign(&keypair_alice);
+
+        assert_eq!(tx.hash(), hash);
+    }
+}
@@ -1,8 +1,7 @@
 use crate::types::{
-    miner, AccountId, Balance, Block, Blockchain, Error, Transaction, TransactionData,
+    miner, AccountId, Balance, Block, BlockError, Blockchain, Transaction, TransactionData,
 };
 use blake2::{Blake2s, Digest};
-use num::BigInt;
 
 pub fn generate_account_id() -> (AccountId, ed25519_dalek::Keypair) {
     let keypair = ed25519_dalek::Keypair::generate(&mut rand::rngs::OsRng {});
@@ -11,22 +10,38 @@ pub fn generate_account_id() -> (AccountId, ed25519_dalek::Keypair) {
     (account_id, keypair)
 }
 
-pub fn append_block(bc: &mut Blockchain) -> Block {
-    let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
-
+/// Coinbase paying the block subsidy to a new account, followed by the
+/// transaction creating that account.
+fn reward_new_account(bc: &Blockchain) -> Vec<Transaction> {
     let (account, keypair) = generate_account_id();
 
-    let tx_create_account = Transaction::new(
-        TransactionData::CreateAccount {
-            account_id: account,
-            public_key: keypair.public,
-        },
-        None,
-    );
+    vec![
+        Transaction::new(
+            TransactionData::Coinbase {
+                to: account.clone(),
+                amount: bc.block_subsidy(bc.len() as u128),
+            },
+            None,
+        ),
+        Transaction::new(
+            TransactionData::CreateAccount {
+                account_id: account,
+                public_key: keypair.public,
+            },
+            None,
+        ),
+    ]
+}
 
-    block.add_transaction(tx_create_account);
+pub fn append_block(bc: &mut Blockchain) -> Block {
+    let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
+
+    for tx in reward_new_account(bc) {
+        block.add_transaction(tx);
+    }
+    block.set_state_root(bc.compute_state_root(&block).unwrap());
 
-    miner::mine(&mut block, bc.get_latest_target());
+    miner::mine_with_clock(&mut block, bc.get_latest_target(), bc.clock());
 
     let block_clone = block.clone();
 
@@ -35,38 +50,37 @@ pub fn append_block(bc: &mut Blockchain) -> Block {
     block_clone
 }
 
+/// Mines a block with `transactions` on top of `bc`. Blocks after genesis
+/// are prefixed with a coinbase rewarding a new account.
 pub fn append_block_with_tx(
     bc: &mut Blockchain,
     nonce: u128,
     transactions: Vec<Transaction>,
-) -> Result<(), Error> {
+) -> Result<(), BlockError> {
     let mut block = Block::new(bc.get_last_block_hash(), bc.get_last_block_number());
     block.set_nonce(nonce);
 
+    if !bc.is_empty() {
+        for tx in reward_new_account(bc) {
+            block.add_transaction(tx);
+        }
+    }
+
     for tx in transactions {
         block.add_transaction(tx);
     }
+    // A block that doesn't execute has no state root, it is rejected for
+    // its transactions first.
+    if let Ok(state_root) = bc.compute_state_root(&block) {
+        block.set_state_root(state_root);
+    }
 
-    miner::mine(&mut block, bc.get_latest_target());
+    miner::mine_with_clock(&mut block, bc.get_latest_target(), bc.clock());
 
     bc.append_block(block)
 }
 
-pub fn to_compact_format(target: BigInt) -> String {
-    let mut hex_target = format!("{:03x}", target);
-
-    let mut len = hex_target.len();
-
-    if len % 2 != 0 {
-        hex_target = "0".to_string() + &*hex_target;
-        len += 1;
-    }
-
-    hex_target = hex_target[0..6].parse().unwrap();
-    let target_compacted = format!("0x{:x}{}", len / 2, hex_target);
-    return target_compacted;
-}
-
+#[allow(clippy::too_many_arguments)]
 pub fn create_accounts_and_transfer(
     bc: &mut Blockchain,
     account_1: AccountId,
@@ -77,11 +91,12 @@ pub fn create_accounts_and_transfer(
     amount_to_send: Balance,
     account_1_keypair: &ed25519_dalek::Keypair,
     account_2_keypair: &ed25519_dalek::Keypair,
-) -> Result<(), Error> {
+) -> Result<(), BlockError> {
     let mut transfer_tx = Transaction::new(
         TransactionData::Transfer {
             to,
             amount: amount_to_send,
+            fee: 0,
         },
         Some(from),
     );
@@ -121,17 +136,9 @@ pub fn create_accounts_and_transfer(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::FromStr;
 
     #[test]
     fn test_generate() {
         dbg!(generate_account_id());
     }
-
-    #[test]
-    fn test_target_compacted() {
-        dbg!(to_compact_format(
-            BigInt::from_str("161578008857017275969393492955354620126364423170461532160").unwrap()
-        ));
-    }
 }